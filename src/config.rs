@@ -1,6 +1,6 @@
 use regex::Regex;
 use serde::{de::Error, Deserialize, Deserializer, Serialize};
-use std::{path::PathBuf, sync::OnceLock};
+use std::{collections::HashMap, net::SocketAddr, ops::Range, path::PathBuf, sync::OnceLock, time::Duration};
 
 static IDENTIFIER_REGEX: OnceLock<Regex> = OnceLock::new();
 
@@ -10,6 +10,54 @@ const fn default_mqtt_port() -> u16 {
 const fn default_sunspec_port() -> u16 {
     502
 }
+const fn default_register_words() -> RegisterWords {
+    RegisterWords(1)
+}
+const fn default_relay_dead_time_ms() -> u64 {
+    500
+}
+const fn default_outbox_max_messages() -> usize {
+    1024
+}
+const fn default_outbox_max_age_ms() -> u64 {
+    24 * 60 * 60 * 1000
+}
+const fn default_state_save_interval_ms() -> u64 {
+    60 * 1000
+}
+const fn default_counter_debounce_ms() -> u64 {
+    20
+}
+const fn default_counter_publish_interval_ms() -> u64 {
+    10 * 1000
+}
+const fn default_counter_rate_timeout_ms() -> u64 {
+    5 * 60 * 1000
+}
+const fn default_onewire_polling_interval_ms() -> u64 {
+    30 * 1000
+}
+const fn default_schedule_enabled() -> bool {
+    true
+}
+fn default_lock_block() -> Vec<LockedCommand> {
+    vec![
+        LockedCommand::Open,
+        LockedCommand::Close,
+        LockedCommand::Tilt,
+        LockedCommand::Press,
+    ]
+}
+
+fn default_state_store_path() -> PathBuf {
+    PathBuf::from("/var/lib/gpio2mqtt/state.json")
+}
+fn default_onewire_sysfs_root() -> PathBuf {
+    PathBuf::from("/sys/bus/w1/devices")
+}
+fn default_http_commands_listen() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 9899))
+}
 fn default_client_id() -> String {
     "gpio2mqtt_bridge".to_owned()
 }
@@ -21,17 +69,242 @@ pub struct Config {
 
     pub covers: Option<Vec<CoverGroup>>,
     pub sunspec: Option<Vec<SunspecConfig>>,
+    pub counters: Option<Vec<CounterConfig>>,
+    pub onewire: Option<OneWireConfig>,
 
     pub broker: String,
 
     #[serde(default = "default_mqtt_port")]
     pub broker_port: u16,
+
+    #[serde(default)]
+    pub log: LogConfig,
+
+    pub http: Option<HttpConfig>,
+
+    #[serde(default)]
+    pub outbox: OutboxConfig,
+
+    pub state_store: Option<StateStoreConfig>,
+
+    pub scheduler: Option<SchedulerConfig>,
+
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+}
+
+/// Local automation: whenever the trigger fires and all conditions hold, the actions are run.
+#[derive(Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+    pub trigger: RuleTriggerConfig,
+    #[serde(default)]
+    pub conditions: Vec<ConditionConfig>,
+    pub actions: Vec<ActionConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RuleTriggerConfig {
+    /// every message on the topic
+    Mqtt {
+        mqtt: String,
+    },
+    /// every new state of the device with the identifier
+    State {
+        state: Identifier,
+    },
+    /// the changes of the input line selected by `on`, its value is `ON` or `OFF`
+    Input {
+        input: InputLineConfig,
+        chip: Option<PathBuf>,
+        #[serde(default)]
+        on: InputEdge,
+    },
+    Schedule(TriggerConfig),
+}
+
+/// Changes of an input line that trigger a rule, `rising` is the change to the active level.
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputEdge {
+    #[default]
+    Rising,
+    Falling,
+    Both,
+}
+
+/// A test of the trigger value, or of the latest state of `device` if given. `field` selects a field of a JSON value,
+/// nested fields are separated by dots.
+#[derive(Deserialize)]
+pub struct ConditionConfig {
+    pub device: Option<Identifier>,
+    pub field: Option<String>,
+    #[serde(flatten)]
+    pub test: ConditionTest,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionTest {
+    Equals(serde_json::Value),
+    NotEquals(serde_json::Value),
+    Below(f64),
+    Above(f64),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ActionConfig {
+    Cover {
+        cover: Identifier,
+        command: CoverCommandConfig,
+    },
+    Gpio {
+        gpio: OutputLineConfig,
+        chip: Option<PathBuf>,
+        active: bool,
+    },
+    Publish {
+        publish: String,
+        payload: String,
+        #[serde(default)]
+        retain: bool,
+    },
+}
+
+/// Cover commands issued locally at fixed times or relative to the sun, the location is only needed for the latter.
+#[derive(Deserialize)]
+pub struct SchedulerConfig {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+}
+
+impl SchedulerConfig {
+    /// Latitude and longitude, if both are configured.
+    pub fn location(&self) -> Option<(f64, f64)> {
+        Some((self.latitude?, self.longitude?))
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ScheduleConfig {
+    pub name: String,
+    pub identifier: Identifier,
+    /// identifiers of the covers or cover groups the command is sent to
+    pub covers: Vec<Identifier>,
+    pub command: CoverCommandConfig,
+    pub trigger: TriggerConfig,
+    /// whether the schedule is enabled until it is switched from homeassistant
+    #[serde(default = "default_schedule_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum TriggerConfig {
+    /// `minute hour day-of-month month day-of-week` in local time
+    Cron { cron: String },
+    Solar {
+        sun: SolarEvent,
+        #[serde(default)]
+        offset_minutes: i64,
+    },
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SolarEvent {
+    /// start of civil twilight
+    Dawn,
+    Sunrise,
+    Sunset,
+    /// end of civil twilight
+    Dusk,
+}
+
+#[derive(Deserialize)]
+pub struct StateStoreConfig {
+    #[serde(default = "default_state_store_path")]
+    pub path: PathBuf,
+    #[serde(default = "default_state_save_interval_ms")]
+    pub save_interval_ms: u64,
+}
+
+#[derive(Deserialize)]
+pub struct OutboxConfig {
+    #[serde(default = "default_outbox_max_messages")]
+    pub max_messages: usize,
+    #[serde(default = "default_outbox_max_age_ms")]
+    pub max_age_ms: u64,
+    pub path: Option<PathBuf>,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: default_outbox_max_messages(),
+            max_age_ms: default_outbox_max_age_ms(),
+            path: None,
+        }
+    }
+}
+
+/// `listen` only serves read-only endpoints, commands are served by a listener of their own.
+#[derive(Deserialize)]
+pub struct HttpConfig {
+    pub listen: SocketAddr,
+    pub commands: Option<HttpCommandsConfig>,
+}
+
+/// The command API, only reachable from the host itself unless `listen` says otherwise.
+#[derive(Deserialize)]
+pub struct HttpCommandsConfig {
+    #[serde(default = "default_http_commands_listen")]
+    pub listen: SocketAddr,
+    /// expected as `Authorization: Bearer <token>`
+    pub token: ApiToken,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Clone)]
+pub struct ApiToken(pub String);
+
+impl<'de> Deserialize<'de> for ApiToken {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let token = String::deserialize(de)?;
+
+        if token.len() < 16 {
+            return Err(Error::custom("API token must be at least 16 characters long"));
+        }
+
+        Ok(ApiToken(token))
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct LogConfig {
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize, Debug, Clone)]
 pub struct Identifier(pub String);
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Device {
     pub identifier: Identifier,
     pub manufacturer: Option<String>,
@@ -58,22 +331,439 @@ impl<'de> Deserialize<'de> for Identifier {
 
 #[derive(Deserialize)]
 pub struct CoverGroup {
+    /// groups with an identifier are exposed as a cover entity that controls all of their devices
+    pub identifier: Option<Identifier>,
+    pub name: Option<String>,
     pub group_gpio_pause_ms: Option<u64>,
     pub devices: Vec<CoverConfig>,
 }
 
-#[derive(Deserialize)]
+impl CoverGroup {
+    pub fn name(&self) -> Option<String> {
+        let identifier = self.identifier.as_ref()?;
+        Some(self.name.clone().unwrap_or_else(|| identifier.0.clone()))
+    }
+}
+
+#[derive(Deserialize, Clone)]
 pub struct CoverConfig {
     pub name: String,
-    pub chip: PathBuf,
-    pub up_pin: u32,
-    pub down_pin: u32,
-    pub stop_pin: u32,
+    pub chip: Option<PathBuf>,
     pub device_gpio_pause_ms: Option<u64>,
     pub device: Device,
+    #[serde(default)]
+    pub command_policy: CommandPolicy,
+    pub command_expiry_ms: Option<u64>,
+    pub open_sensor_pin: Option<InputLineConfig>,
+    pub closed_sensor_pin: Option<InputLineConfig>,
+    /// time after which a cover that did not reach the end stop it was moving to is considered faulty
+    pub end_stop_timeout_ms: Option<u64>,
+    pub tilt: Option<TiltConfig>,
+    #[serde(default)]
+    pub locks: Vec<LockConfig>,
+    #[serde(flatten)]
+    pub driver: CoverDriverConfig,
+}
+
+impl CoverConfig {
+    pub fn lines(&self) -> Vec<(&'static str, &LineRef)> {
+        let mut lines = match &self.driver {
+            CoverDriverConfig::Relay(relay) => vec![("up_pin", &relay.up_pin.line), ("down_pin", &relay.down_pin.line)],
+            CoverDriverConfig::Stateless(stateless) => vec![
+                ("up_pin", &stateless.up_pin.line),
+                ("down_pin", &stateless.down_pin.line),
+                ("stop_pin", &stateless.stop_pin.line),
+            ],
+        };
+
+        lines.extend(
+            self.open_sensor_pin
+                .iter()
+                .map(|sensor| ("open_sensor_pin", &sensor.line)),
+        );
+        lines.extend(
+            self.closed_sensor_pin
+                .iter()
+                .map(|sensor| ("closed_sensor_pin", &sensor.line)),
+        );
+        lines.extend(self.locks.iter().filter_map(|lock| match &lock.source {
+            LockSource::Pin { pin } => Some(("lock pin", &pin.line)),
+            LockSource::Mqtt { .. } => None,
+        }));
+
+        lines
+    }
+
+    pub fn has_end_stops(&self) -> bool {
+        self.open_sensor_pin.is_some() || self.closed_sensor_pin.is_some()
+    }
+
+    /// Whether the state of the cover is tracked, either from end stops or from its travel time.
+    pub fn reports_state(&self) -> bool {
+        self.has_end_stops() || self.travel_time().is_some()
+    }
+
+    /// Time a full travel takes, if it is known.
+    pub fn travel_time(&self) -> Option<Duration> {
+        match &self.driver {
+            CoverDriverConfig::Relay(relay) => Some(Duration::from_millis(relay.travel_time_ms)),
+            CoverDriverConfig::Stateless(_) => None,
+        }
+    }
+
+    /// Commands of `fifo` covers expire after 30 seconds unless configured otherwise, so that a cover that was down
+    /// doesn't replay every command it missed once it is back.
+    pub fn command_expiry(&self) -> Option<Duration> {
+        match (self.command_expiry_ms, self.command_policy) {
+            (Some(expiry_ms), _) => Some(Duration::from_millis(expiry_ms)),
+            (None, CommandPolicy::Fifo) => Some(Duration::from_secs(30)),
+            (None, _) => None,
+        }
+    }
+
+    pub fn end_stop_timeout(&self) -> Duration {
+        match (self.end_stop_timeout_ms, self.travel_time()) {
+            (Some(timeout_ms), _) => Duration::from_millis(timeout_ms),
+            (None, Some(travel_time)) => travel_time + Duration::from_secs(5),
+            (None, None) => Duration::from_secs(120),
+        }
+    }
+
+    pub fn buttons(&self) -> &[CoverButtonConfig] {
+        match &self.driver {
+            CoverDriverConfig::Relay(_) => &[],
+            CoverDriverConfig::Stateless(stateless) => &stateless.buttons.0,
+        }
+    }
+}
+
+/// Selected by the `driver` field of a cover: `relay` covers are driven by two relays, `stateless` covers by pressing
+/// the buttons of a remote. Covers without a `driver` are `stateless`, as they were before relays were supported.
+#[derive(Clone)]
+pub enum CoverDriverConfig {
+    Relay(RelayCoverConfig),
+    Stateless(StatelessCoverConfig),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CoverDriver {
+    Relay,
+    Stateless,
+}
+
+impl<'de> Deserialize<'de> for CoverDriverConfig {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut fields = serde_yaml::Mapping::deserialize(de)?;
+        let driver = match fields.remove("driver") {
+            Some(driver) => CoverDriver::deserialize(driver).map_err(Error::custom)?,
+            None => CoverDriver::Stateless,
+        };
+
+        let fields = serde_yaml::Value::Mapping(fields);
+        match driver {
+            CoverDriver::Relay => RelayCoverConfig::deserialize(fields).map(CoverDriverConfig::Relay),
+            CoverDriver::Stateless => StatelessCoverConfig::deserialize(fields).map(CoverDriverConfig::Stateless),
+        }
+        .map_err(Error::custom)
+    }
+}
+
+/// Unknown fields are rejected, which also catches misspelled fields of the cover itself.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StatelessCoverConfig {
+    pub up_pin: OutputLineConfig,
+    pub down_pin: OutputLineConfig,
+    pub stop_pin: OutputLineConfig,
+    #[serde(default)]
+    pub press_patterns: PressPatternsConfig,
+    #[serde(default)]
+    pub buttons: CoverButtons,
+}
+
+/// A hold-to-run cover, the relay of a direction is energized for as long as the cover moves.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RelayCoverConfig {
+    pub up_pin: OutputLineConfig,
+    pub down_pin: OutputLineConfig,
+    /// time to travel from one end to the other, the relays are de-energized afterwards
+    pub travel_time_ms: u64,
+    /// time both relays stay de-energized before the direction is changed
+    #[serde(default = "default_relay_dead_time_ms")]
+    pub dead_time_ms: u64,
+}
+
+/// Slats of venetian blinds, tilted by short movements of the cover.
+#[derive(Deserialize, Clone)]
+pub struct TiltConfig {
+    /// time the slats take to turn from fully closed to fully open
+    pub tilt_time_ms: u64,
+    /// played on the up pin to open the slats fully, instead of a timed movement
+    pub open_pattern: Option<PressPattern>,
+    /// played on the down pin to close the slats fully, instead of a timed movement
+    pub close_pattern: Option<PressPattern>,
+}
+
+/// A condition that keeps a cover from moving, e.g. rain or an open door.
+#[derive(Deserialize, Clone)]
+pub struct LockConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: LockSource,
+    /// commands that are rejected while the lock is active
+    #[serde(default = "default_lock_block")]
+    pub block: Vec<LockedCommand>,
+    /// command that is issued once the lock becomes active
+    pub force: Option<CoverCommandConfig>,
+}
+
+/// A lock is active while its input line is active, or while the last message on its topic equals `payload`.
+/// MQTT locks start released unless `initially_active` is set, as no message may arrive after a restart.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum LockSource {
+    Pin {
+        pin: InputLineConfig,
+    },
+    Mqtt {
+        topic: String,
+        payload: String,
+        #[serde(default)]
+        initially_active: bool,
+    },
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockedCommand {
+    Open,
+    Close,
+    Tilt,
+    Press,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverCommandConfig {
+    Open,
+    Close,
+    Stop,
+    TiltOpen,
+    TiltClose,
+}
+
+/// A GPIO line, either its offset on the configured chip or its name, which is looked up on every chip.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum LineRef {
+    Offset(u32),
+    Name(String),
+}
+
+/// An output line, either given as a plain line reference or with its electrical options.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "OutputLineConfigRepr")]
+pub struct OutputLineConfig {
+    pub line: LineRef,
+    pub active_low: bool,
+    pub drive: LineDrive,
+    /// logical level of the line while it is not pressed
+    pub idle: LineLevel,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OutputLineConfigRepr {
+    Line(LineRef),
+    Detailed {
+        #[serde(alias = "offset")]
+        line: LineRef,
+        #[serde(default)]
+        active_low: bool,
+        #[serde(default)]
+        drive: LineDrive,
+        #[serde(default)]
+        idle: LineLevel,
+    },
+}
+
+impl From<OutputLineConfigRepr> for OutputLineConfig {
+    fn from(repr: OutputLineConfigRepr) -> Self {
+        match repr {
+            OutputLineConfigRepr::Line(line) => Self {
+                line,
+                active_low: false,
+                drive: LineDrive::default(),
+                idle: LineLevel::default(),
+            },
+            OutputLineConfigRepr::Detailed { line, active_low, drive, idle } => Self { line, active_low, drive, idle },
+        }
+    }
+}
+
+/// An input line, either given as a plain line reference or with its options.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "InputLineConfigRepr")]
+pub struct InputLineConfig {
+    pub line: LineRef,
+    pub active_low: bool,
 }
 
 #[derive(Deserialize)]
+#[serde(untagged)]
+enum InputLineConfigRepr {
+    Line(LineRef),
+    Detailed {
+        #[serde(alias = "offset")]
+        line: LineRef,
+        #[serde(default)]
+        active_low: bool,
+    },
+}
+
+impl From<InputLineConfigRepr> for InputLineConfig {
+    fn from(repr: InputLineConfigRepr) -> Self {
+        match repr {
+            InputLineConfigRepr::Line(line) => Self { line, active_low: false },
+            InputLineConfigRepr::Detailed { line, active_low } => Self { line, active_low },
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineDrive {
+    #[default]
+    PushPull,
+    OpenDrain,
+    OpenSource,
+}
+
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineLevel {
+    #[default]
+    Low,
+    High,
+}
+
+/// Press patterns of the `Up`, `Down` and `Stop` pins, `default` applies to every command without its own pattern.
+#[derive(Deserialize, Default, Clone)]
+pub struct PressPatternsConfig {
+    pub default: Option<PressPattern>,
+    pub open: Option<PressPattern>,
+    pub close: Option<PressPattern>,
+    pub stop: Option<PressPattern>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CoverButtonConfig {
+    pub name: String,
+    pub identifier: Identifier,
+    pub pin: CoverPin,
+    pub pattern: PressPattern,
+}
+
+/// The buttons of a cover, their identifiers are unique within the cover as they address the button.
+#[derive(Default, Clone)]
+pub struct CoverButtons(pub Vec<CoverButtonConfig>);
+
+impl<'de> Deserialize<'de> for CoverButtons {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let buttons = Vec::<CoverButtonConfig>::deserialize(de)?;
+
+        for (index, button) in buttons.iter().enumerate() {
+            if buttons[..index]
+                .iter()
+                .any(|other| other.identifier.0 == button.identifier.0)
+            {
+                return Err(Error::custom(format!(
+                    "duplicate button identifier {}",
+                    button.identifier.0
+                )));
+            }
+        }
+
+        Ok(CoverButtons(buttons))
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverPin {
+    Up,
+    Down,
+    Stop,
+}
+
+/// Alternating high and low durations in milliseconds, starting and ending with high.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PressPattern(pub Vec<u64>);
+
+impl PressPattern {
+    const MAX_TOTAL_MS: u64 = 30 * 1000;
+
+    pub fn durations(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.0.iter().copied().map(Duration::from_millis)
+    }
+}
+
+impl Default for PressPattern {
+    fn default() -> Self {
+        PressPattern(vec![100])
+    }
+}
+
+impl<'de> Deserialize<'de> for PressPattern {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let durations = Vec::<u64>::deserialize(de)?;
+
+        if durations.len() % 2 == 0 {
+            return Err(Error::custom(
+                "press pattern must alternate high and low durations, starting and ending with high",
+            ));
+        }
+
+        if durations.contains(&0) {
+            return Err(Error::custom("press pattern durations must not be zero"));
+        }
+
+        if durations.iter().sum::<u64>() > Self::MAX_TOTAL_MS {
+            return Err(Error::custom(format!(
+                "press pattern must not take longer than {} ms",
+                Self::MAX_TOTAL_MS
+            )));
+        }
+
+        Ok(PressPattern(durations))
+    }
+}
+
+/// How a cover treats commands that arrive while a previous one is still waiting to be issued.
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandPolicy {
+    /// only the most recent command is issued
+    #[default]
+    LatestWins,
+    /// every command is issued in order
+    Fifo,
+    /// every command is issued in order, unless it repeats the previous pending one
+    CollapseDuplicates,
+}
+
+#[derive(Deserialize, Clone)]
 pub struct SunspecConfig {
     pub name: String,
     pub device: Device,
@@ -81,4 +771,241 @@ pub struct SunspecConfig {
     #[serde(default = "default_sunspec_port")]
     pub host_port: u16,
     pub device_polling_delay_ms: u64,
+    #[serde(default)]
+    pub power_registers: PowerRegistersConfig,
+    pub publish: Option<PublishConfig>,
+    #[serde(default)]
+    pub state_topics: StateTopics,
+}
+
+/// Temperature sensors on the 1-Wire bus, every sensor found in `sysfs_root` at startup is published.
+#[derive(Deserialize, Clone)]
+pub struct OneWireConfig {
+    #[serde(default = "default_onewire_sysfs_root")]
+    pub sysfs_root: PathBuf,
+    #[serde(default = "default_onewire_polling_interval_ms")]
+    pub polling_interval_ms: u64,
+    /// names of the sensors by their id, e.g. `28-0316a2795bff`
+    #[serde(default)]
+    pub names: HashMap<String, String>,
+}
+
+/// A meter with an S0 pulse output wired to an input line.
+#[derive(Deserialize, Clone)]
+pub struct CounterConfig {
+    pub name: String,
+    pub device: Device,
+    pub chip: Option<PathBuf>,
+    pub pin: InputLineConfig,
+    pub meter: MeterKind,
+    /// pulses per kWh for energy meters, per m³ for water and gas meters
+    pub pulses_per_unit: PulsesPerUnit,
+    /// pulses closer to the previous one are ignored as bounces
+    #[serde(default = "default_counter_debounce_ms")]
+    pub debounce_ms: u64,
+    #[serde(default = "default_counter_publish_interval_ms")]
+    pub publish_interval_ms: u64,
+    /// the rate drops to zero when no pulse was counted for this long
+    #[serde(default = "default_counter_rate_timeout_ms")]
+    pub rate_timeout_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MeterKind {
+    Energy,
+    Water,
+    Gas,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PulsesPerUnit(pub f64);
+
+impl<'de> Deserialize<'de> for PulsesPerUnit {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match f64::deserialize(de)? {
+            pulses if pulses.is_finite() && pulses > 0.0 => Ok(PulsesPerUnit(pulses)),
+            _ => Err(Error::custom("pulses per unit must be a positive number")),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StateTopics {
+    #[default]
+    Json,
+    PerField,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct PublishConfig {
+    #[serde(default)]
+    pub min_interval_ms: u64,
+    pub heartbeat_interval_ms: Option<u64>,
+    #[serde(default)]
+    pub deadbands: Deadbands,
+}
+
+/// Deadbands by the state field they apply to. The fields are checked against the state when the device is set up,
+/// so that a typo doesn't silently disable the deadband.
+#[derive(Deserialize, Default, Clone)]
+pub struct Deadbands(pub HashMap<String, Deadband>);
+
+impl Deadbands {
+    /// The deadband of the state field with the name, if one is configured.
+    pub fn get(&self, field: &str) -> Option<Deadband> {
+        self.0.get(field).copied()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Deadband {
+    Absolute(f64),
+    Percentage(f64),
+}
+
+impl<'de> Deserialize<'de> for Deadband {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Absolute(f64),
+            Percentage(String),
+        }
+
+        let deadband = match Repr::deserialize(de)? {
+            Repr::Absolute(value) => Deadband::Absolute(value),
+            Repr::Percentage(s) => {
+                let value = s
+                    .strip_suffix('%')
+                    .and_then(|value| value.trim().parse().ok())
+                    .ok_or_else(|| Error::custom("deadband must be a number or a percentage like \"5%\""))?;
+
+                Deadband::Percentage(value)
+            },
+        };
+
+        match deadband {
+            Deadband::Absolute(value) | Deadband::Percentage(value) if value < 0.0 => {
+                Err(Error::custom("deadband must not be negative"))
+            },
+            deadband => Ok(deadband),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct PowerRegistersConfig {
+    pub active_power: Option<RegisterConfig>,
+    pub apparent_power: Option<RegisterConfig>,
+    pub grid_power: Option<RegisterConfig>,
+}
+
+#[derive(Clone)]
+pub struct RegisterConfig {
+    pub address: u16,
+    pub words: RegisterWords,
+}
+
+/// Fields of a `RegisterConfig`, the register is only accepted if it ends within the address space.
+#[derive(Deserialize)]
+struct RegisterFields {
+    address: u16,
+    #[serde(default = "default_register_words")]
+    words: RegisterWords,
+}
+
+impl<'de> Deserialize<'de> for RegisterConfig {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let RegisterFields { address, words } = RegisterFields::deserialize(de)?;
+
+        if address.checked_add(words.0).is_none() {
+            return Err(Error::custom(format!(
+                "register {address} with {} words exceeds the highest address {}",
+                words.0,
+                u16::MAX - 1
+            )));
+        }
+
+        Ok(RegisterConfig { address, words })
+    }
+}
+
+impl RegisterConfig {
+    pub fn register(&self) -> Range<u16> {
+        self.address..self.address + self.words.0
+    }
+}
+
+#[derive(Clone)]
+pub struct RegisterWords(pub u16);
+
+impl<'de> Deserialize<'de> for RegisterWords {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match u16::deserialize(de)? {
+            words @ (1 | 2) => Ok(RegisterWords(words)),
+            _ => Err(Error::custom("register must be 1 (16 bit) or 2 (32 bit) words wide")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cover(driver_fields: &str) -> Result<CoverConfig, serde_yaml::Error> {
+        serde_yaml::from_str(&format!(
+            "name: Velux 1\ndevice:\n    identifier: velux_integra_1\n{driver_fields}"
+        ))
+    }
+
+    #[test]
+    fn cover_without_driver_is_stateless() {
+        let conf = cover("up_pin: 2\nstop_pin: 3\ndown_pin: 4\n").unwrap();
+
+        assert!(matches!(conf.driver, CoverDriverConfig::Stateless(_)));
+        assert_eq!(conf.travel_time(), None);
+    }
+
+    #[test]
+    fn cover_selects_its_driver() {
+        let conf = cover("driver: stateless\nup_pin: 2\nstop_pin: 3\ndown_pin: 4\n").unwrap();
+        assert!(matches!(conf.driver, CoverDriverConfig::Stateless(_)));
+
+        let conf = cover("driver: relay\nup_pin: 5\ndown_pin: 6\ntravel_time_ms: 25000\n").unwrap();
+        assert!(matches!(conf.driver, CoverDriverConfig::Relay(_)));
+        assert_eq!(conf.travel_time(), Some(Duration::from_secs(25)));
+    }
+
+    #[test]
+    fn cover_rejects_unknown_drivers_and_fields() {
+        assert!(cover("driver: awning\nup_pin: 2\nstop_pin: 3\ndown_pin: 4\n").is_err());
+        assert!(cover("up_pin: 2\nstop_pin: 3\ndown_pin: 4\ntravel_time_ms: 25000\n").is_err());
+        assert!(cover("driver: relay\nup_pin: 5\ndown_pin: 6\nstop_pin: 7\ntravel_time_ms: 25000\n").is_err());
+        assert!(cover("up_pin: 2\nstop_pin: 3\ndown_pin: 4\nend_stop_timout_ms: 1000\n").is_err());
+    }
+
+    #[test]
+    fn register_must_fit_the_address_space() {
+        let register = |yaml| serde_yaml::from_str::<RegisterConfig>(yaml).map(|conf| conf.register());
+
+        assert_eq!(register("address: 1066").unwrap(), 1066..1067);
+        assert_eq!(register("address: 65533\nwords: 2").unwrap(), 65533..65535);
+        assert!(register("address: 65534\nwords: 2").is_err());
+        assert!(register("address: 65535").is_err());
+        assert!(register("address: 1066\nwords: 3").is_err());
+    }
 }
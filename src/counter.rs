@@ -0,0 +1,94 @@
+use crate::{
+    config::{CounterConfig, MeterKind},
+    gpio::Pulses,
+};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+impl MeterKind {
+    /// Unit of the total, pulses per unit are configured for this unit.
+    pub fn total_unit(self) -> &'static str {
+        match self {
+            MeterKind::Energy => "kWh",
+            MeterKind::Water | MeterKind::Gas => "m³",
+        }
+    }
+
+    pub fn rate_unit(self) -> &'static str {
+        match self {
+            MeterKind::Energy => "W",
+            MeterKind::Water => "L/min",
+            MeterKind::Gas => "m³/h",
+        }
+    }
+
+    /// Factor from total units per second to rate units.
+    fn rate_factor(self) -> f64 {
+        match self {
+            MeterKind::Energy => 1000.0 * 3600.0,
+            MeterKind::Water => 1000.0 * 60.0,
+            MeterKind::Gas => 3600.0,
+        }
+    }
+}
+
+/// `pulses` includes the pulses counted before the last restart, `stale` is only set for restored states.
+#[derive(Serialize, Debug, Clone)]
+pub struct CounterState {
+    pub pulses: u64,
+    pub total: f64,
+    pub rate: f64,
+    pub stale: bool,
+}
+
+/// Converts the pulses of an S0 meter to its total and rate.
+#[derive(Debug, Clone)]
+pub struct Counter {
+    meter: MeterKind,
+    pulses_per_unit: f64,
+    rate_timeout: Duration,
+    /// pulses counted before the last restart
+    offset: u64,
+    /// highest count reported so far, the total is never reported lower than that
+    high_water: u64,
+}
+
+impl Counter {
+    pub fn from_config(conf: &CounterConfig, offset: u64) -> Self {
+        Self {
+            meter: conf.meter,
+            pulses_per_unit: conf.pulses_per_unit.0,
+            rate_timeout: Duration::from_millis(conf.rate_timeout_ms),
+            offset,
+            high_water: offset,
+        }
+    }
+
+    /// The rate is derived from the time between the last two pulses. Once the next pulse is overdue, the time since
+    /// the last pulse is used instead, so that the rate falls until it drops to zero after the rate timeout.
+    pub fn state(&mut self, pulses: &Pulses) -> CounterState {
+        let count = (self.offset + pulses.count).max(self.high_water);
+        self.high_water = count;
+        let since_last = pulses.last.map(|last| Instant::now().duration_since(last));
+
+        let rate = match (pulses.interval, since_last) {
+            (Some(interval), Some(since_last)) if !interval.is_zero() && since_last < self.rate_timeout => {
+                let seconds = interval.max(since_last).as_secs_f64();
+                self.meter.rate_factor() / self.pulses_per_unit / seconds
+            },
+            _ => 0.0,
+        };
+
+        CounterState {
+            pulses: count,
+            total: round(count as f64 / self.pulses_per_unit, 3),
+            rate: round(rate, 2),
+            stale: false,
+        }
+    }
+}
+
+fn round(value: f64, decimals: i32) -> f64 {
+    let factor = 10f64.powi(decimals);
+    (value * factor).round() / factor
+}
@@ -0,0 +1,307 @@
+use super::{
+    lock::{LockStatus, Locks},
+    CoverAction, CoverCommand,
+};
+use crate::config::{CommandPolicy, CoverConfig};
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Formatter},
+    sync::Mutex,
+};
+use tokio::{
+    sync::Notify,
+    time::{Duration, Instant},
+};
+
+/// Upper bound of pending commands per cover, so that a stuck cover doesn't replay minutes of button presses.
+const MAX_PENDING: usize = 16;
+
+/// A command or button press that was accepted but will never be issued.
+#[derive(Debug, Clone)]
+pub enum DiscardedCommand {
+    Superseded { cmd: CoverAction, by: CoverAction },
+    Merged(CoverAction),
+    Overflow(CoverAction),
+    Expired { cmd: CoverAction, age: Duration },
+    Blocked { cmd: CoverAction, lock: String },
+}
+
+impl Display for DiscardedCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscardedCommand::Superseded { cmd, by } => write!(f, "dropped {cmd}, superseded by {by}"),
+            DiscardedCommand::Merged(cmd) => write!(f, "merged {cmd} into the pending {cmd}"),
+            DiscardedCommand::Overflow(cmd) => write!(f, "dropped {cmd}, too many pending commands"),
+            DiscardedCommand::Expired { cmd, age } => write!(f, "dropped {cmd}, expired after {age:?}"),
+            DiscardedCommand::Blocked { cmd, lock } => write!(f, "blocked {cmd}, locked by {lock}"),
+        }
+    }
+}
+
+struct QueuedCommand {
+    cmd: CoverAction,
+    queued_at: Instant,
+}
+
+/// Pending commands of a single cover, shared between the command sources and the cover event loop.
+pub struct CommandQueue {
+    pending: Mutex<VecDeque<QueuedCommand>>,
+    notify: Notify,
+    drained: Notify,
+    policy: CommandPolicy,
+    expiry: Option<Duration>,
+    locks: Mutex<Locks>,
+}
+
+impl CommandQueue {
+    pub fn new(policy: CommandPolicy, expiry: Option<Duration>, locks: Locks) -> Self {
+        Self {
+            pending: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            drained: Notify::new(),
+            policy,
+            expiry,
+            locks: Mutex::new(locks),
+        }
+    }
+
+    pub fn from_config(conf: &CoverConfig) -> Self {
+        Self::new(
+            conf.command_policy,
+            conf.command_expiry(),
+            Locks::from_config(&conf.locks),
+        )
+    }
+
+    fn blocked(&self, cmd: &CoverAction) -> Option<DiscardedCommand> {
+        let locks = self.locks.lock().unwrap();
+        let lock = locks.blocking(cmd)?;
+
+        Some(DiscardedCommand::Blocked { cmd: cmd.clone(), lock: lock.to_owned() })
+    }
+
+    /// Activates or releases one of the locks, returns the command to force if it just became active.
+    pub fn set_lock(&self, index: usize, active: bool) -> Option<CoverCommand> {
+        self.locks.lock().unwrap().set_active(index, active)
+    }
+
+    pub fn lock_status(&self) -> LockStatus {
+        self.locks.lock().unwrap().status()
+    }
+
+    /// Queues `cmd` according to the policy, returns the commands that will not be issued because of it.
+    pub fn push(&self, cmd: CoverAction) -> Vec<DiscardedCommand> {
+        if let Some(blocked) = self.blocked(&cmd) {
+            return vec![blocked];
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        let mut discarded = Vec::new();
+
+        match self.policy {
+            CommandPolicy::LatestWins => {
+                discarded.extend(
+                    pending
+                        .drain(..)
+                        .map(|queued| DiscardedCommand::Superseded { cmd: queued.cmd, by: cmd.clone() }),
+                );
+            },
+            CommandPolicy::Fifo => {},
+            CommandPolicy::CollapseDuplicates => {
+                if pending.back().is_some_and(|queued| queued.cmd == cmd) {
+                    discarded.push(DiscardedCommand::Merged(cmd));
+                    return discarded;
+                }
+            },
+        }
+
+        if pending.len() >= MAX_PENDING {
+            if let Some(queued) = pending.pop_front() {
+                discarded.push(DiscardedCommand::Overflow(queued.cmd));
+            }
+        }
+
+        pending.push_back(QueuedCommand { cmd, queued_at: Instant::now() });
+        drop(pending);
+
+        self.notify.notify_one();
+        discarded
+    }
+
+    /// Waits until at least one command is pending.
+    pub async fn ready(&self) {
+        loop {
+            let notified = self.notify.notified();
+
+            if !self.is_empty() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Waits until every pending command has been taken.
+    pub async fn drained(&self) {
+        loop {
+            let drained = self.drained.notified();
+
+            if self.is_empty() {
+                return;
+            }
+
+            drained.await;
+        }
+    }
+
+    /// Takes the oldest pending command, commands that expired or got locked while waiting are returned as `Err`.
+    pub fn pop(&self) -> Option<Result<CoverAction, DiscardedCommand>> {
+        let queued = {
+            let mut pending = self.pending.lock().unwrap();
+            let queued = pending.pop_front()?;
+
+            if pending.is_empty() {
+                self.drained.notify_one();
+            }
+
+            queued
+        };
+
+        let age = queued.queued_at.elapsed();
+
+        if let Some(blocked) = self.blocked(&queued.cmd) {
+            return Some(Err(blocked));
+        }
+
+        match self.expiry {
+            Some(expiry) if age > expiry => Some(Err(DiscardedCommand::Expired { cmd: queued.cmd, age })),
+            _ => Some(Ok(queued.cmd)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(policy: CommandPolicy, expiry: Option<Duration>) -> CommandQueue {
+        CommandQueue::new(policy, expiry, Locks::from_config(&[]))
+    }
+
+    fn pop_all(queue: &CommandQueue) -> Vec<Result<CoverAction, DiscardedCommand>> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    fn issued(queue: &CommandQueue) -> Vec<CoverAction> {
+        pop_all(queue).into_iter().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn latest_wins_supersedes_pending_commands() {
+        let queue = queue(CommandPolicy::LatestWins, None);
+
+        assert!(queue.push(CoverCommand::Open.into()).is_empty());
+        let discarded = queue.push(CoverCommand::Close.into());
+
+        assert!(matches!(
+            discarded.as_slice(),
+            [DiscardedCommand::Superseded {
+                cmd: CoverAction::Command(CoverCommand::Open),
+                by: CoverAction::Command(CoverCommand::Close),
+            }]
+        ));
+        assert_eq!(issued(&queue), [CoverCommand::Close.into()]);
+    }
+
+    #[test]
+    fn fifo_issues_commands_in_order() {
+        let queue = queue(CommandPolicy::Fifo, Some(Duration::from_secs(30)));
+
+        for cmd in [CoverCommand::Open, CoverCommand::Stop, CoverCommand::Open] {
+            assert!(queue.push(cmd.into()).is_empty());
+        }
+
+        assert_eq!(queue.len(), 3);
+        assert_eq!(
+            issued(&queue),
+            [
+                CoverCommand::Open.into(),
+                CoverCommand::Stop.into(),
+                CoverCommand::Open.into()
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn fifo_drops_expired_commands() {
+        let queue = queue(CommandPolicy::Fifo, Some(Duration::from_millis(1)));
+
+        queue.push(CoverCommand::Open.into());
+        queue.push(CoverCommand::Close.into());
+        std::thread::sleep(std::time::Duration::from_millis(5));
+
+        for popped in pop_all(&queue) {
+            assert!(matches!(popped, Err(DiscardedCommand::Expired { age, .. }) if age > Duration::from_millis(1)));
+        }
+
+        queue.push(CoverCommand::Stop.into());
+        assert!(matches!(
+            queue.pop(),
+            Some(Ok(CoverAction::Command(CoverCommand::Stop)))
+        ));
+    }
+
+    #[test]
+    fn collapse_duplicates_merges_repeated_commands() {
+        let queue = queue(CommandPolicy::CollapseDuplicates, None);
+
+        assert!(queue.push(CoverCommand::Open.into()).is_empty());
+        assert!(matches!(
+            queue.push(CoverCommand::Open.into()).as_slice(),
+            [DiscardedCommand::Merged(CoverAction::Command(CoverCommand::Open))]
+        ));
+        assert!(queue.push(CoverCommand::Stop.into()).is_empty());
+        assert!(queue.push(CoverCommand::Open.into()).is_empty());
+
+        assert_eq!(
+            issued(&queue),
+            [
+                CoverCommand::Open.into(),
+                CoverCommand::Stop.into(),
+                CoverCommand::Open.into()
+            ]
+        );
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest_command() {
+        let queue = queue(CommandPolicy::Fifo, None);
+
+        for position in 0..MAX_PENDING as u8 {
+            assert!(queue.push(CoverCommand::TiltPosition(position + 1).into()).is_empty());
+        }
+
+        let discarded = queue.push(CoverCommand::Close.into());
+
+        assert!(matches!(
+            discarded.as_slice(),
+            [DiscardedCommand::Overflow(CoverAction::Command(
+                CoverCommand::TiltPosition(1)
+            ))]
+        ));
+        assert_eq!(queue.len(), MAX_PENDING);
+
+        let issued = issued(&queue);
+        assert_eq!(issued.first(), Some(&CoverCommand::TiltPosition(2).into()));
+        assert_eq!(issued.last(), Some(&CoverCommand::Close.into()));
+    }
+}
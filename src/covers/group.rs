@@ -0,0 +1,73 @@
+use super::position::CoverState;
+use serde::Serialize;
+
+#[derive(Serialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupState {
+    Open,
+    Opening,
+    Closed,
+    Closing,
+    /// the members are neither all open nor all closed
+    Mixed,
+}
+
+impl GroupState {
+    /// Combines the states of the group members that report one, `None` if none of them does.
+    pub fn aggregate(states: impl IntoIterator<Item = CoverState>) -> Option<Self> {
+        let states: Vec<_> = states.into_iter().collect();
+
+        if states.is_empty() {
+            None
+        } else if states.contains(&CoverState::Opening) {
+            Some(GroupState::Opening)
+        } else if states.contains(&CoverState::Closing) {
+            Some(GroupState::Closing)
+        } else if states.iter().all(|&state| state == CoverState::Open) {
+            Some(GroupState::Open)
+        } else if states.iter().all(|&state| state == CoverState::Closed) {
+            Some(GroupState::Closed)
+        } else {
+            Some(GroupState::Mixed)
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct GroupStatus {
+    pub state: GroupState,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CoverState::*;
+
+    #[test]
+    fn members_in_the_same_end_position() {
+        assert_eq!(GroupState::aggregate([Open, Open]), Some(GroupState::Open));
+        assert_eq!(GroupState::aggregate([Closed]), Some(GroupState::Closed));
+    }
+
+    #[test]
+    fn mixed_members() {
+        assert_eq!(GroupState::aggregate([Open, Closed]), Some(GroupState::Mixed));
+        assert_eq!(GroupState::aggregate([Open, Stopped]), Some(GroupState::Mixed));
+        assert_eq!(GroupState::aggregate([Stopped, Stopped]), Some(GroupState::Mixed));
+    }
+
+    #[test]
+    fn moving_members_take_precedence() {
+        assert_eq!(
+            GroupState::aggregate([Open, Opening, Closed]),
+            Some(GroupState::Opening)
+        );
+        assert_eq!(GroupState::aggregate([Closing, Stopped]), Some(GroupState::Closing));
+        assert_eq!(GroupState::aggregate([Closing, Opening]), Some(GroupState::Opening));
+    }
+
+    #[test]
+    fn no_member_reports_a_state() {
+        assert_eq!(GroupState::aggregate([]), None);
+    }
+}
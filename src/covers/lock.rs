@@ -0,0 +1,163 @@
+use super::{CoverAction, CoverCommand};
+use crate::config::{LockConfig, LockSource, LockedCommand};
+use serde::Serialize;
+
+impl LockedCommand {
+    /// Stopping is never locked, whatever keeps a cover from moving should not keep it from stopping.
+    fn matches(self, action: &CoverAction) -> bool {
+        match action {
+            CoverAction::Command(CoverCommand::Open) => self == LockedCommand::Open,
+            CoverAction::Command(CoverCommand::Close) => self == LockedCommand::Close,
+            CoverAction::Command(CoverCommand::Stop | CoverCommand::TiltStop) => false,
+            CoverAction::Command(_) => self == LockedCommand::Tilt,
+            CoverAction::Press(_) => self == LockedCommand::Press,
+        }
+    }
+}
+
+struct Lock {
+    name: String,
+    block: Vec<LockedCommand>,
+    force: Option<CoverCommand>,
+    active: bool,
+}
+
+/// The locks of a single cover and whether they are currently active.
+pub struct Locks(Vec<Lock>);
+
+#[derive(Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct LockStatus {
+    pub locked: bool,
+    pub active_locks: Vec<String>,
+}
+
+impl Locks {
+    pub fn from_config(locks: &[LockConfig]) -> Self {
+        Self(
+            locks
+                .iter()
+                .map(|lock| Lock {
+                    name: lock.name.clone(),
+                    block: lock.block.clone(),
+                    force: lock.force.map(CoverCommand::from),
+                    active: matches!(lock.source, LockSource::Mqtt { initially_active: true, .. }),
+                })
+                .collect(),
+        )
+    }
+
+    /// Name of the first active lock that rejects `action`.
+    pub fn blocking(&self, action: &CoverAction) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|lock| lock.active && lock.block.iter().any(|locked| locked.matches(action)))
+            .map(|lock| lock.name.as_str())
+    }
+
+    /// Activates or releases the lock at `index`, returns the command to force if it just became active.
+    pub fn set_active(&mut self, index: usize, active: bool) -> Option<CoverCommand> {
+        let lock = self.0.get_mut(index)?;
+
+        if lock.active == active {
+            return None;
+        }
+
+        lock.active = active;
+        lock.force.filter(|_| active)
+    }
+
+    pub fn status(&self) -> LockStatus {
+        let active_locks: Vec<_> = self
+            .0
+            .iter()
+            .filter(|lock| lock.active)
+            .map(|lock| lock.name.clone())
+            .collect();
+
+        LockStatus { locked: !active_locks.is_empty(), active_locks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locks(yaml: &str) -> Locks {
+        Locks::from_config(&serde_yaml::from_str::<Vec<LockConfig>>(yaml).unwrap())
+    }
+
+    const RAIN_AND_DOOR: &str = r#"
+- name: rain
+  topic: weather/rain
+  payload: "ON"
+  block: [open]
+  force: close
+- name: door
+  topic: house/door
+  payload: open
+  initially_active: true
+"#;
+
+    #[test]
+    fn initially_active_locks() {
+        let locks = locks(RAIN_AND_DOOR);
+
+        assert_eq!(
+            locks.status(),
+            LockStatus { locked: true, active_locks: vec!["door".to_string()] }
+        );
+    }
+
+    #[test]
+    fn locking_forces_the_configured_command_once() {
+        let mut locks = locks(RAIN_AND_DOOR);
+
+        assert_eq!(locks.set_active(0, true), Some(CoverCommand::Close));
+        assert_eq!(locks.set_active(0, true), None);
+        assert_eq!(locks.status().active_locks, ["rain", "door"]);
+    }
+
+    #[test]
+    fn unlocking_releases_commands() {
+        let mut locks = locks(RAIN_AND_DOOR);
+
+        assert_eq!(locks.blocking(&CoverCommand::Close.into()), Some("door"));
+        assert_eq!(locks.set_active(1, false), None);
+        assert_eq!(locks.blocking(&CoverCommand::Close.into()), None);
+        assert_eq!(locks.status(), LockStatus { locked: false, active_locks: vec![] });
+        assert_eq!(locks.set_active(2, true), None);
+    }
+
+    #[test]
+    fn active_locks_reject_blocked_commands() {
+        let mut locks = locks(RAIN_AND_DOOR);
+        locks.set_active(1, false);
+        locks.set_active(0, true);
+
+        assert_eq!(locks.blocking(&CoverCommand::Open.into()), Some("rain"));
+        assert_eq!(locks.blocking(&CoverCommand::Close.into()), None);
+        assert_eq!(locks.blocking(&CoverCommand::TiltPosition(50).into()), None);
+        assert_eq!(locks.blocking(&CoverAction::Press("up".to_string())), None);
+    }
+
+    #[test]
+    fn stopping_is_never_blocked() {
+        let locks = locks(
+            r#"
+- name: maintenance
+  topic: house/maintenance
+  payload: "ON"
+  block: [open, close, tilt, press]
+  initially_active: true
+"#,
+        );
+
+        assert_eq!(locks.blocking(&CoverCommand::TiltOpen.into()), Some("maintenance"));
+        assert_eq!(
+            locks.blocking(&CoverAction::Press("up".to_string())),
+            Some("maintenance")
+        );
+        assert_eq!(locks.blocking(&CoverCommand::Stop.into()), None);
+        assert_eq!(locks.blocking(&CoverCommand::TiltStop.into()), None);
+    }
+}
@@ -1,5 +1,15 @@
+pub mod command_queue;
+pub mod group;
+pub mod lock;
+pub mod position;
+pub mod relay;
 pub mod stateless_gpio;
+pub mod tilt;
 
+use crate::{
+    config::{CoverCommandConfig, CoverConfig, CoverDriverConfig},
+    gpio,
+};
 use std::{
     fmt::{Display, Formatter},
     str::FromStr,
@@ -11,6 +21,88 @@ pub enum CoverCommand {
     Open,
     Close,
     Stop,
+    TiltOpen,
+    TiltClose,
+    TiltStop,
+    TiltPosition(u8),
+}
+
+impl From<CoverCommandConfig> for CoverCommand {
+    fn from(cmd: CoverCommandConfig) -> Self {
+        match cmd {
+            CoverCommandConfig::Open => CoverCommand::Open,
+            CoverCommandConfig::Close => CoverCommand::Close,
+            CoverCommandConfig::Stop => CoverCommand::Stop,
+            CoverCommandConfig::TiltOpen => CoverCommand::TiltOpen,
+            CoverCommandConfig::TiltClose => CoverCommand::TiltClose,
+        }
+    }
+}
+
+impl CoverCommand {
+    /// Command for a tilt position as sent by homeassistant, the end positions fully open or close the slats.
+    pub fn tilt(position: u8) -> Self {
+        match position {
+            0 => CoverCommand::TiltClose,
+            100.. => CoverCommand::TiltOpen,
+            position => CoverCommand::TiltPosition(position),
+        }
+    }
+
+    pub fn is_tilt(&self) -> bool {
+        matches!(
+            self,
+            CoverCommand::TiltOpen | CoverCommand::TiltClose | CoverCommand::TiltStop | CoverCommand::TiltPosition(_)
+        )
+    }
+}
+
+pub enum Driver {
+    Stateless(stateless_gpio::Cover),
+    Relay(relay::Cover),
+}
+
+impl Driver {
+    pub fn from_config(conf: &CoverConfig) -> Result<Self, gpio::Error> {
+        let chip = conf.chip.as_deref();
+
+        let tilt = conf.tilt.as_ref();
+
+        match &conf.driver {
+            CoverDriverConfig::Stateless(stateless) => Ok(Driver::Stateless(stateless_gpio::Cover::from_config(
+                chip, stateless, tilt,
+            )?)),
+            CoverDriverConfig::Relay(relay) => Ok(Driver::Relay(relay::Cover::from_config(chip, relay, tilt)?)),
+        }
+    }
+}
+
+/// Anything a cover can be asked to do, either a regular command or pressing one of its configured buttons.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum CoverAction {
+    Command(CoverCommand),
+    Press(String),
+}
+
+impl CoverAction {
+    pub fn is_tilt(&self) -> bool {
+        matches!(self, CoverAction::Command(cmd) if cmd.is_tilt())
+    }
+}
+
+impl From<CoverCommand> for CoverAction {
+    fn from(cmd: CoverCommand) -> Self {
+        CoverAction::Command(cmd)
+    }
+}
+
+impl Display for CoverAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoverAction::Command(cmd) => write!(f, "{cmd}"),
+            CoverAction::Press(button) => write!(f, "PRESS {button}"),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -25,7 +117,13 @@ impl FromStr for CoverCommand {
             "OPEN" => Ok(CoverCommand::Open),
             "CLOSE" => Ok(CoverCommand::Close),
             "STOP" => Ok(CoverCommand::Stop),
-            _ => Err(CoverCommandParseError),
+            "TILT_OPEN" => Ok(CoverCommand::TiltOpen),
+            "TILT_CLOSE" => Ok(CoverCommand::TiltClose),
+            "TILT_STOP" => Ok(CoverCommand::TiltStop),
+            _ => match s.strip_prefix("TILT_").map(str::parse::<u8>) {
+                Some(Ok(position @ 0..=100)) => Ok(CoverCommand::TiltPosition(position)),
+                _ => Err(CoverCommandParseError),
+            },
         }
     }
 }
@@ -36,6 +134,10 @@ impl Display for CoverCommand {
             CoverCommand::Open => write!(f, "OPEN"),
             CoverCommand::Close => write!(f, "CLOSE"),
             CoverCommand::Stop => write!(f, "STOP"),
+            CoverCommand::TiltOpen => write!(f, "TILT_OPEN"),
+            CoverCommand::TiltClose => write!(f, "TILT_CLOSE"),
+            CoverCommand::TiltStop => write!(f, "TILT_STOP"),
+            CoverCommand::TiltPosition(position) => write!(f, "TILT_{position}"),
         }
     }
 }
@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+/// What the driver of a cover is currently doing.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Movement {
+    Opening,
+    Closing,
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverState {
+    Open,
+    Opening,
+    Closed,
+    Closing,
+    Stopped,
+}
+
+/// State of a cover as published to homeassistant, the position is only known for covers with a known travel time.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CoverStatus {
+    pub state: CoverState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<u8>,
+    #[serde(default)]
+    pub fault: bool,
+}
+
+impl CoverStatus {
+    /// The position a cover with this status is at, the ends are known even without an estimated position.
+    pub fn known_position(&self) -> Option<f64> {
+        match (self.position, self.state) {
+            (Some(position), _) => Some(position as f64),
+            (None, CoverState::Open) => Some(100.0),
+            (None, CoverState::Closed) => Some(0.0),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Estimates the position of a cover (0 is closed, 100 is open) from its movements and end stops.
+pub struct PositionTracker {
+    travel_time: Option<Duration>,
+    movement: Movement,
+    since: Instant,
+    /// position when the current movement started
+    start_position: Option<f64>,
+    fault: bool,
+}
+
+impl PositionTracker {
+    pub fn new(travel_time: Option<Duration>) -> Self {
+        Self {
+            travel_time,
+            movement: Movement::Stopped,
+            since: Instant::now(),
+            start_position: None,
+            fault: false,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.movement != Movement::Stopped
+    }
+
+    pub fn position(&self, now: Instant) -> Option<f64> {
+        let start_position = self.start_position?;
+
+        // without a travel time only the end stops are known, the position is lost once the cover moves
+        let Some(travel_time) = self.travel_time else {
+            return (self.movement == Movement::Stopped).then_some(start_position);
+        };
+
+        let travelled = now.duration_since(self.since).as_secs_f64() / travel_time.as_secs_f64() * 100.0;
+
+        Some(match self.movement {
+            Movement::Opening => (start_position + travelled).min(100.0),
+            Movement::Closing => (start_position - travelled).max(0.0),
+            Movement::Stopped => start_position,
+        })
+    }
+
+    pub fn set_movement(&mut self, movement: Movement, now: Instant) {
+        let travelled_fully = self
+            .travel_time
+            .is_some_and(|travel_time| now.duration_since(self.since) >= travel_time);
+
+        // a full uninterrupted travel ends at an end position, even if the position was not known before
+        self.start_position = match (self.position(now), self.movement) {
+            (Some(position), _) => Some(position),
+            (None, Movement::Opening) if travelled_fully => Some(100.0),
+            (None, Movement::Closing) if travelled_fully => Some(0.0),
+            (None, _) => None,
+        };
+
+        self.movement = movement;
+        self.since = now;
+    }
+
+    /// Re-calibrates the position once an end stop is reached.
+    pub fn end_reached(&mut self, open: bool, now: Instant) {
+        self.start_position = Some(if open { 100.0 } else { 0.0 });
+        self.movement = Movement::Stopped;
+        self.since = now;
+        self.fault = false;
+    }
+
+    /// Continues from the position a cover was at before a restart, unless it moved since.
+    pub fn restore(&mut self, position: f64) {
+        if self.start_position.is_none() && !self.is_moving() {
+            self.start_position = Some(position.clamp(0.0, 100.0));
+        }
+    }
+
+    /// The cover did not reach the end stop it was moving to, its position is unknown from now on.
+    pub fn fault(&mut self, now: Instant) {
+        self.start_position = None;
+        self.movement = Movement::Stopped;
+        self.since = now;
+        self.fault = true;
+    }
+
+    pub fn status(&self, now: Instant, open_active: bool, closed_active: bool) -> CoverStatus {
+        let position = self.position(now);
+
+        let state = if open_active {
+            CoverState::Open
+        } else if closed_active {
+            CoverState::Closed
+        } else {
+            match (self.movement, position) {
+                (Movement::Opening, _) => CoverState::Opening,
+                (Movement::Closing, _) => CoverState::Closing,
+                (Movement::Stopped, Some(position)) if position >= 100.0 => CoverState::Open,
+                (Movement::Stopped, Some(position)) if position <= 0.0 => CoverState::Closed,
+                (Movement::Stopped, _) => CoverState::Stopped,
+            }
+        };
+
+        CoverStatus {
+            state,
+            position: self.travel_time.and(position).map(|position| position.round() as u8),
+            fault: self.fault,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_is_lost_when_moving_without_travel_time() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::new(None);
+
+        tracker.end_reached(false, now);
+        assert_eq!(tracker.status(now, false, true).state, CoverState::Closed);
+
+        tracker.set_movement(Movement::Opening, now);
+        assert_eq!(tracker.status(now, false, false).state, CoverState::Opening);
+
+        tracker.set_movement(Movement::Stopped, now + Duration::from_secs(120));
+        let status = tracker.status(now + Duration::from_secs(120), false, false);
+        assert_eq!(status.state, CoverState::Stopped);
+        assert_eq!(tracker.position(now + Duration::from_secs(120)), None);
+        assert!(!status.fault);
+    }
+
+    #[test]
+    fn position_is_estimated_from_travel_time() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::new(Some(Duration::from_secs(20)));
+
+        tracker.end_reached(false, now);
+        tracker.set_movement(Movement::Opening, now);
+        assert_eq!(
+            tracker.status(now + Duration::from_secs(5), false, false).position,
+            Some(25)
+        );
+
+        tracker.set_movement(Movement::Stopped, now + Duration::from_secs(25));
+        let status = tracker.status(now + Duration::from_secs(25), false, false);
+        assert_eq!(status.state, CoverState::Open);
+        assert_eq!(status.position, Some(100));
+    }
+
+    #[test]
+    fn full_travel_calibrates_an_unknown_position() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::new(Some(Duration::from_secs(20)));
+
+        tracker.set_movement(Movement::Closing, now);
+        assert_eq!(tracker.status(now, false, false).position, None);
+
+        tracker.set_movement(Movement::Stopped, now + Duration::from_secs(20));
+        assert_eq!(
+            tracker.status(now + Duration::from_secs(20), false, false).state,
+            CoverState::Closed
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use super::{
+    stateless_gpio::gpio_press,
+    tilt::{Tilt, TiltMovement},
+};
+use crate::{
+    config::{RelayCoverConfig, TiltConfig},
+    gpio::{self, OutputLine},
+};
+use std::path::Path;
+use tokio::time::{self, Duration, Instant};
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// A hold-to-run cover driven by one relay per direction.
+/// Both relays are never energized at the same time, before the direction changes both stay
+/// de-energized for the configured dead time.
+pub struct Cover {
+    up: OutputLine,
+    down: OutputLine,
+    pub travel_time: Duration,
+    dead_time: Duration,
+    moving: Option<Direction>,
+    stopped_at: Option<Instant>,
+    tilt: Option<Tilt>,
+}
+
+impl Cover {
+    pub fn from_config(
+        chip: Option<&Path>,
+        conf: &RelayCoverConfig,
+        tilt: Option<&TiltConfig>,
+    ) -> Result<Self, gpio::Error> {
+        Ok(Self {
+            up: OutputLine::request(chip, &conf.up_pin)?,
+            down: OutputLine::request(chip, &conf.down_pin)?,
+            travel_time: Duration::from_millis(conf.travel_time_ms),
+            dead_time: Duration::from_millis(conf.dead_time_ms),
+            moving: None,
+            stopped_at: None,
+            tilt: tilt.map(Tilt::from_config),
+        })
+    }
+
+    pub fn has_tilt(&self) -> bool {
+        self.tilt.is_some()
+    }
+
+    pub fn tilt_position(&self) -> Option<u8> {
+        self.tilt.as_ref().and_then(Tilt::position)
+    }
+
+    pub fn moving(&self) -> Option<Direction> {
+        self.moving
+    }
+
+    fn line(&self, direction: Direction) -> &OutputLine {
+        match direction {
+            Direction::Up => &self.up,
+            Direction::Down => &self.down,
+        }
+    }
+
+    /// Starts a travel in `direction`, which turns the slats in an unknown way.
+    pub async fn travel(&mut self, direction: Direction) -> Result<(), gpio_cdev::Error> {
+        if let Some(tilt) = &mut self.tilt {
+            tilt.invalidate();
+        }
+
+        self.start(direction).await
+    }
+
+    /// Energizes the relay of `direction`, stopping any movement in the other direction first.
+    pub async fn start(&mut self, direction: Direction) -> Result<(), gpio_cdev::Error> {
+        if self.moving == Some(direction) {
+            return Ok(());
+        }
+
+        self.stop()?;
+
+        if let Some(stopped_at) = self.stopped_at {
+            time::sleep_until(stopped_at + self.dead_time).await;
+        }
+
+        self.line(direction).set_active(true)?;
+        self.moving = Some(direction);
+
+        Ok(())
+    }
+
+    /// Turns the slats to `position` by short movements, covers without tilt ignore this.
+    pub async fn tilt_to(&mut self, position: u8) -> Result<(), gpio_cdev::Error> {
+        let Some(movements) = self.tilt.as_mut().map(|tilt| tilt.movements_to(position)) else {
+            return Ok(());
+        };
+
+        for movement in movements {
+            let result = self.tilt_step(movement).await;
+            self.stop()?;
+
+            if let Err(e) = result {
+                if let Some(tilt) = &mut self.tilt {
+                    tilt.invalidate();
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn tilt_step(&mut self, movement: TiltMovement) -> Result<(), gpio_cdev::Error> {
+        match movement {
+            TiltMovement::Pulse(direction, pattern) => {
+                self.start(direction).await?;
+                gpio_press(self.line(direction), &pattern).await
+            },
+            TiltMovement::Timed(direction, duration) => {
+                self.start(direction).await?;
+                time::sleep(duration).await;
+                Ok(())
+            },
+        }
+    }
+
+    /// De-energizes both relays, the second one is released even if releasing the first one failed.
+    pub fn stop(&mut self) -> Result<(), gpio_cdev::Error> {
+        let up = self.up.set_active(false);
+        let down = self.down.set_active(false);
+        up.and(down)?;
+
+        if self.moving.take().is_some() {
+            self.stopped_at = Some(Instant::now());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Cover {
+    fn drop(&mut self) {
+        // never leave a motor running, e.g. if the event loop crashed
+        let _ = self.stop();
+    }
+}
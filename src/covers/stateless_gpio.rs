@@ -1,64 +1,173 @@
-use super::CoverCommand;
-use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use super::{
+    relay::Direction,
+    tilt::{Tilt, TiltMovement},
+    CoverAction, CoverCommand,
+};
+use crate::{
+    config::{CoverButtonConfig, CoverPin, OutputLineConfig, PressPattern, StatelessCoverConfig, TiltConfig},
+    gpio::{self, OutputLine},
+};
 use std::path::Path;
-use tokio::time::Duration;
+use tokio::time;
 
-async fn gpio_sim_short_press(handle: &LineHandle) -> Result<(), gpio_cdev::Error> {
-    handle.set_value(1)?;
-    tokio::time::sleep(Duration::from_millis(100)).await;
-    handle.set_value(0)?;
-    Ok(())
+pub(super) async fn gpio_press(line: &OutputLine, pattern: &PressPattern) -> Result<(), gpio_cdev::Error> {
+    for (i, duration) in pattern.durations().enumerate() {
+        line.set_active(i % 2 == 0)?;
+        time::sleep(duration).await;
+    }
+
+    line.set_active(false)
 }
 
 pub struct Cover {
-    pub up: LineHandle,
-    pub down: LineHandle,
-    pub stop: LineHandle,
+    pub up: OutputLine,
+    pub down: OutputLine,
+    pub stop: OutputLine,
+    open_pattern: PressPattern,
+    close_pattern: PressPattern,
+    stop_pattern: PressPattern,
+    buttons: Vec<CoverButtonConfig>,
+    tilt: Option<Tilt>,
 }
 
 impl Cover {
-    pub fn from_chip_offsets<P: AsRef<Path>>(
-        chip_path: P,
-        up_offset: u32,
-        down_offset: u32,
-        stop_offset: u32,
-    ) -> Result<Self, gpio_cdev::Error> {
-        const CONSUMER: &str = "gpio2mqtt";
+    pub fn from_lines(
+        chip: Option<&Path>,
+        up: &OutputLineConfig,
+        down: &OutputLineConfig,
+        stop: &OutputLineConfig,
+    ) -> Result<Self, gpio::Error> {
+        Ok(Self {
+            up: OutputLine::request(chip, up)?,
+            down: OutputLine::request(chip, down)?,
+            stop: OutputLine::request(chip, stop)?,
+            open_pattern: PressPattern::default(),
+            close_pattern: PressPattern::default(),
+            stop_pattern: PressPattern::default(),
+            buttons: Vec::new(),
+            tilt: None,
+        })
+    }
 
-        let mut chip = Chip::new(chip_path)?;
+    pub fn from_config(
+        chip: Option<&Path>,
+        conf: &StatelessCoverConfig,
+        tilt: Option<&TiltConfig>,
+    ) -> Result<Self, gpio::Error> {
+        let patterns = &conf.press_patterns;
+        let pattern = |pattern: &Option<PressPattern>| {
+            pattern
+                .as_ref()
+                .or(patterns.default.as_ref())
+                .cloned()
+                .unwrap_or_default()
+        };
 
-        let up = chip
-            .get_line(up_offset)?
-            .request(LineRequestFlags::OUTPUT, 0, CONSUMER)?;
+        Ok(Self {
+            open_pattern: pattern(&patterns.open),
+            close_pattern: pattern(&patterns.close),
+            stop_pattern: pattern(&patterns.stop),
+            buttons: conf.buttons.0.clone(),
+            tilt: tilt.map(Tilt::from_config),
+            ..Self::from_lines(chip, &conf.up_pin, &conf.down_pin, &conf.stop_pin)?
+        })
+    }
 
-        let down = chip
-            .get_line(down_offset)?
-            .request(LineRequestFlags::OUTPUT, 0, CONSUMER)?;
+    fn line(&self, pin: CoverPin) -> &OutputLine {
+        match pin {
+            CoverPin::Up => &self.up,
+            CoverPin::Down => &self.down,
+            CoverPin::Stop => &self.stop,
+        }
+    }
 
-        let stop = chip
-            .get_line(stop_offset)?
-            .request(LineRequestFlags::OUTPUT, 0, CONSUMER)?;
+    pub fn has_tilt(&self) -> bool {
+        self.tilt.is_some()
+    }
 
-        Ok(Self { up, down, stop })
+    pub fn tilt_position(&self) -> Option<u8> {
+        self.tilt.as_ref().and_then(Tilt::position)
+    }
+
+    pub async fn perform(&mut self, action: &CoverAction) -> Result<(), gpio_cdev::Error> {
+        match action {
+            CoverAction::Command(cmd) => self.issue_command(*cmd).await,
+            CoverAction::Press(identifier) => self.press(identifier).await,
+        }
+    }
+
+    /// Plays the pattern of the button with the given identifier, unknown buttons are ignored.
+    pub async fn press(&self, identifier: &str) -> Result<(), gpio_cdev::Error> {
+        match self.buttons.iter().find(|button| button.identifier.0 == identifier) {
+            Some(button) => gpio_press(self.line(button.pin), &button.pattern).await,
+            None => Ok(()),
+        }
     }
 
-    pub async fn issue_command(&self, cmd: CoverCommand) -> Result<(), gpio_cdev::Error> {
+    pub async fn issue_command(&mut self, cmd: CoverCommand) -> Result<(), gpio_cdev::Error> {
         match cmd {
-            CoverCommand::Open => self.move_up().await,
-            CoverCommand::Close => self.move_down().await,
-            CoverCommand::Stop => self.stop().await,
+            CoverCommand::Open => {
+                self.invalidate_tilt();
+                self.move_up().await
+            },
+            CoverCommand::Close => {
+                self.invalidate_tilt();
+                self.move_down().await
+            },
+            CoverCommand::Stop | CoverCommand::TiltStop => self.stop().await,
+            CoverCommand::TiltOpen => self.tilt_to(100).await,
+            CoverCommand::TiltClose => self.tilt_to(0).await,
+            CoverCommand::TiltPosition(position) => self.tilt_to(position).await,
         }
     }
 
+    /// Turns the slats to `position`, covers without tilt ignore this.
+    pub async fn tilt_to(&mut self, position: u8) -> Result<(), gpio_cdev::Error> {
+        let Some(tilt) = &mut self.tilt else {
+            return Ok(());
+        };
+
+        for movement in tilt.movements_to(position) {
+            let result = match movement {
+                TiltMovement::Pulse(Direction::Up, pattern) => gpio_press(&self.up, &pattern).await,
+                TiltMovement::Pulse(Direction::Down, pattern) => gpio_press(&self.down, &pattern).await,
+                TiltMovement::Timed(direction, duration) => self.move_for(direction, duration).await,
+            };
+
+            if let Err(e) = result {
+                self.invalidate_tilt();
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalidate_tilt(&mut self) {
+        if let Some(tilt) = &mut self.tilt {
+            tilt.invalidate();
+        }
+    }
+
+    async fn move_for(&self, direction: Direction, duration: time::Duration) -> Result<(), gpio_cdev::Error> {
+        match direction {
+            Direction::Up => self.move_up().await?,
+            Direction::Down => self.move_down().await?,
+        }
+
+        time::sleep(duration).await;
+        self.stop().await
+    }
+
     pub async fn move_up(&self) -> Result<(), gpio_cdev::Error> {
-        gpio_sim_short_press(&self.up).await
+        gpio_press(&self.up, &self.open_pattern).await
     }
 
     pub async fn move_down(&self) -> Result<(), gpio_cdev::Error> {
-        gpio_sim_short_press(&self.down).await
+        gpio_press(&self.down, &self.close_pattern).await
     }
 
     pub async fn stop(&self) -> Result<(), gpio_cdev::Error> {
-        gpio_sim_short_press(&self.stop).await
+        gpio_press(&self.stop, &self.stop_pattern).await
     }
 }
@@ -0,0 +1,155 @@
+use super::relay::Direction;
+use crate::config::{PressPattern, TiltConfig};
+use tokio::time::Duration;
+
+/// A single step of tilting the slats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TiltMovement {
+    /// play the pattern on the pin of the direction
+    Pulse(Direction, PressPattern),
+    /// move in the direction for the duration, then stop
+    Timed(Direction, Duration),
+}
+
+/// Tilt position of the slats (0 is closed, 100 is open), as far as it is known.
+pub struct Tilt {
+    tilt_time: Duration,
+    open_pattern: Option<PressPattern>,
+    close_pattern: Option<PressPattern>,
+    position: Option<u8>,
+}
+
+impl Tilt {
+    pub fn from_config(conf: &TiltConfig) -> Self {
+        Self {
+            tilt_time: Duration::from_millis(conf.tilt_time_ms),
+            open_pattern: conf.open_pattern.clone(),
+            close_pattern: conf.close_pattern.clone(),
+            position: None,
+        }
+    }
+
+    pub fn position(&self) -> Option<u8> {
+        self.position
+    }
+
+    /// The cover moved, which turns the slats in an unknown way.
+    pub fn invalidate(&mut self) {
+        self.position = None;
+    }
+
+    /// Movements that turn the slats to `target`, the slats are closed first if their position is unknown.
+    pub fn movements_to(&mut self, target: u8) -> Vec<TiltMovement> {
+        let target = target.min(100);
+        let mut movements = Vec::new();
+
+        match (target, &self.open_pattern, &self.close_pattern) {
+            (100, Some(pattern), _) => movements.push(TiltMovement::Pulse(Direction::Up, pattern.clone())),
+            (0, _, Some(pattern)) => movements.push(TiltMovement::Pulse(Direction::Down, pattern.clone())),
+            _ => {
+                let current = match self.position {
+                    Some(position) => position,
+                    None => {
+                        movements.push(match &self.close_pattern {
+                            Some(pattern) => TiltMovement::Pulse(Direction::Down, pattern.clone()),
+                            None => TiltMovement::Timed(Direction::Down, self.tilt_time),
+                        });
+                        0
+                    },
+                };
+
+                let direction = if target > current {
+                    Direction::Up
+                } else {
+                    Direction::Down
+                };
+                let duration = self.tilt_time * u32::from(target.abs_diff(current)) / 100;
+
+                if !duration.is_zero() {
+                    movements.push(TiltMovement::Timed(direction, duration));
+                }
+            },
+        }
+
+        self.position = Some(target);
+        movements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TiltMovement::{Pulse, Timed};
+
+    fn tilt(open_pattern: Option<PressPattern>, close_pattern: Option<PressPattern>) -> Tilt {
+        Tilt::from_config(&TiltConfig { tilt_time_ms: 1000, open_pattern, close_pattern })
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn unknown_position_closes_the_slats_first() {
+        let mut tilt = tilt(None, None);
+
+        assert_eq!(
+            tilt.movements_to(30),
+            [Timed(Direction::Down, ms(1000)), Timed(Direction::Up, ms(300))]
+        );
+        assert_eq!(tilt.position(), Some(30));
+    }
+
+    #[test]
+    fn moves_relative_to_the_known_position() {
+        let mut tilt = tilt(None, None);
+        tilt.movements_to(30);
+
+        assert_eq!(tilt.movements_to(80), [Timed(Direction::Up, ms(500))]);
+        assert_eq!(tilt.movements_to(60), [Timed(Direction::Down, ms(200))]);
+        assert_eq!(tilt.position(), Some(60));
+    }
+
+    #[test]
+    fn already_at_the_target() {
+        let mut tilt = tilt(None, None);
+        tilt.movements_to(40);
+
+        assert_eq!(tilt.movements_to(40), []);
+        assert_eq!(tilt.position(), Some(40));
+    }
+
+    #[test]
+    fn full_range_without_patterns() {
+        let mut tilt = tilt(None, None);
+        tilt.movements_to(0);
+
+        assert_eq!(tilt.movements_to(100), [Timed(Direction::Up, ms(1000))]);
+        assert_eq!(tilt.movements_to(0), [Timed(Direction::Down, ms(1000))]);
+    }
+
+    #[test]
+    fn full_range_with_patterns() {
+        let open = PressPattern(vec![500]);
+        let close = PressPattern(vec![200, 100, 200]);
+        let mut tilt = tilt(Some(open.clone()), Some(close.clone()));
+
+        assert_eq!(tilt.movements_to(100), [Pulse(Direction::Up, open)]);
+        assert_eq!(tilt.movements_to(0), [Pulse(Direction::Down, close.clone())]);
+
+        tilt.invalidate();
+        assert_eq!(
+            tilt.movements_to(50),
+            [Pulse(Direction::Down, close), Timed(Direction::Up, ms(500))]
+        );
+    }
+
+    #[test]
+    fn targets_above_100_are_fully_open() {
+        let mut tilt = tilt(None, None);
+        tilt.movements_to(0);
+
+        assert_eq!(tilt.movements_to(250), [Timed(Direction::Up, ms(1000))]);
+        assert_eq!(tilt.position(), Some(100));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{
+    config, covers,
+    covers::{command_queue::CommandQueue, position::CoverState},
+    eventloop::Message,
+    gpio, http, metrics, mqtt, onewire, outbox, rules,
+};
+use paho_mqtt::AsyncClient;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+/// Updates the fields of the latest state of `topic`, keeping fields reported by other sources.
+pub fn merge_state(latest_states: &http::LatestStates, topic: &str, update: serde_json::Value) {
+    let mut latest_states = latest_states.lock().unwrap();
+
+    match (latest_states.get_mut(topic), update) {
+        (Some(serde_json::Value::Object(fields)), serde_json::Value::Object(update)) => fields.extend(update),
+        (_, update) => {
+            latest_states.insert(topic.to_owned(), update);
+        },
+    }
+}
+
+/// The covers of the bridge, keyed by their command topics.
+pub struct Covers {
+    pub queues: HashMap<String, Arc<CommandQueue>>,
+    /// button command topic to the cover topic and the button identifier
+    pub buttons: HashMap<String, (String, String)>,
+    pub state_topics: HashMap<String, String>,
+    /// tilt command topic to the cover topic
+    pub tilts: HashMap<String, String>,
+    pub tilt_topics: HashMap<String, String>,
+    pub lock_topics: HashMap<String, String>,
+    /// group topics and the topics of their members
+    pub group_members: Vec<(String, Vec<String>)>,
+    /// latest state of every cover that reports its state, to aggregate the states of groups
+    pub states: HashMap<String, CoverState>,
+}
+
+/// The schedules that can be switched on and off through MQTT.
+pub struct ScheduleSwitches {
+    /// command topic to the index of the schedule and its state topic
+    pub topics: HashMap<String, (usize, String)>,
+    pub control: mpsc::Sender<(usize, bool)>,
+}
+
+/// Handles the messages of the event loops: publishes states, queues cover commands and runs the triggered rules.
+pub struct Dispatcher {
+    pub client_id: String,
+    pub mqtt_client: AsyncClient,
+    pub outbox: outbox::Outbox,
+    pub latest_states: http::LatestStates,
+    pub covers: Covers,
+    pub schedules: ScheduleSwitches,
+    pub sunspec_state_topics: HashMap<String, config::StateTopics>,
+    pub rules: rules::Rules,
+    pub rule_outputs: Vec<gpio::OutputLine>,
+    pub rule_topics: HashSet<String>,
+    pub lock_topics: HashSet<String>,
+}
+
+impl Dispatcher {
+    pub async fn handle(&mut self, message: Message) {
+        match message {
+            Message::SunspecMeasurement(topic, measurement) => {
+                let state = mqtt::SunspecState::from(measurement);
+                metrics::metrics().record_sunspec_state(&topic, &state);
+                let state = mqtt::Timestamped::now(&state);
+                self.latest_states
+                    .lock()
+                    .unwrap()
+                    .insert(topic.clone(), serde_json::to_value(&state).unwrap());
+
+                let messages = mqtt::state_messages(&topic, &state, self.sunspec_state_topics[&topic]);
+                self.outbox.publish(&self.mqtt_client, messages).await;
+                self.state_changed(&topic).await;
+            },
+            Message::CounterState(topic, state) => {
+                let state = mqtt::Timestamped::now(&state);
+                self.latest_states
+                    .lock()
+                    .unwrap()
+                    .insert(topic.clone(), serde_json::to_value(&state).unwrap());
+
+                let messages = mqtt::state_messages(&topic, &state, config::StateTopics::Json);
+                self.outbox.publish(&self.mqtt_client, messages).await;
+                self.state_changed(&topic).await;
+            },
+            Message::OneWireTemperature(topic, temperature) => {
+                let state = onewire::TemperatureState { temperature, stale: false };
+                let state = mqtt::Timestamped::now(&state);
+                self.latest_states
+                    .lock()
+                    .unwrap()
+                    .insert(topic.clone(), serde_json::to_value(&state).unwrap());
+
+                let messages = mqtt::state_messages(&topic, &state, config::StateTopics::Json);
+                self.outbox.publish(&self.mqtt_client, messages).await;
+                self.state_changed(&topic).await;
+            },
+            Message::CoverStatus(topic, status) => self.cover_status(topic, status).await,
+            Message::CoverTilt(topic, position) => {
+                merge_state(&self.latest_states, &topic, json!({ "tilt": position }));
+
+                let tilt_message = mqtt::tilt_message(&self.covers.tilt_topics[&topic], position);
+                self.outbox.publish(&self.mqtt_client, [tilt_message]).await;
+                self.state_changed(&topic).await;
+            },
+            Message::CoverLock(topic, index, active) => self.cover_lock(topic, index, active).await,
+            Message::RuleInput(index, active) => {
+                let event = rules::Event::Input { rule: index, active };
+                let triggered = self.rules.evaluate(&event, &self.latest_states.lock().unwrap());
+                self.run_rule_actions(triggered).await;
+            },
+            Message::RuleSchedule(index) => {
+                let triggered = self
+                    .rules
+                    .evaluate(&rules::Event::Schedule(index), &self.latest_states.lock().unwrap());
+                self.run_rule_actions(triggered).await;
+            },
+            Message::Diagnostic(source, message) => {
+                let diagnostic = mqtt::diagnostic_message(&self.client_id, &source, &message);
+                self.outbox.publish(&self.mqtt_client, [diagnostic]).await;
+            },
+            Message::MqttEvent(msg) => self.mqtt_message(msg).await,
+            Message::CoverCommand(topic, cmd) => {
+                if !self.covers.queues.contains_key(&topic) {
+                    warn!(topic, "HTTP command error: unknown cover");
+                    return;
+                }
+
+                self.push_cover_action(&topic, cmd.into()).await;
+            },
+        }
+    }
+
+    async fn cover_status(&mut self, topic: String, status: covers::position::CoverStatus) {
+        self.covers.states.insert(topic.clone(), status.state);
+
+        let status = mqtt::Timestamped::now(&status);
+        let mut update = serde_json::to_value(&status).unwrap();
+        update["stale"] = json!(false);
+        merge_state(&self.latest_states, &topic, update);
+
+        let state_topic = &self.covers.state_topics[&topic];
+        let messages = mqtt::state_messages(state_topic, &status, config::StateTopics::Json);
+        self.outbox.publish(&self.mqtt_client, messages).await;
+        self.state_changed(&topic).await;
+
+        let groups: Vec<_> = self
+            .covers
+            .group_members
+            .iter()
+            .filter(|(_, members)| members.contains(&topic))
+            .cloned()
+            .collect();
+
+        for (group_topic, members) in groups {
+            let member_states = members
+                .iter()
+                .filter_map(|member| self.covers.states.get(member).copied());
+            let Some(state) = covers::group::GroupState::aggregate(member_states) else {
+                continue;
+            };
+
+            let status = covers::group::GroupStatus { state };
+            let status = mqtt::Timestamped::now(&status);
+            let mut update = serde_json::to_value(&status).unwrap();
+            update["stale"] = json!(false);
+            merge_state(&self.latest_states, &group_topic, update);
+
+            let state_topic = &self.covers.state_topics[&group_topic];
+            let messages = mqtt::state_messages(state_topic, &status, config::StateTopics::Json);
+            self.outbox.publish(&self.mqtt_client, messages).await;
+            self.state_changed(&group_topic).await;
+        }
+    }
+
+    async fn cover_lock(&mut self, topic: String, index: usize, active: bool) {
+        let Some(queue) = self.covers.queues.get(&topic) else {
+            return;
+        };
+
+        let previous = queue.lock_status();
+        let force = queue.set_lock(index, active);
+        let status = queue.lock_status();
+
+        if status == previous {
+            return;
+        }
+
+        info!(topic, locked = status.locked, "Active locks: {:?}", status.active_locks);
+
+        let status = mqtt::Timestamped::now(&status);
+        merge_state(&self.latest_states, &topic, serde_json::to_value(&status).unwrap());
+
+        let messages = mqtt::state_messages(&self.covers.lock_topics[&topic], &status, config::StateTopics::Json);
+        self.outbox.publish(&self.mqtt_client, messages).await;
+        self.state_changed(&topic).await;
+
+        let Some(force) = force else {
+            return;
+        };
+
+        info!(topic, "Forcing {force}");
+        self.push_cover_action(&topic, force.into()).await;
+    }
+
+    async fn mqtt_message(&mut self, msg: paho_mqtt::Message) {
+        let payload = match std::str::from_utf8(msg.payload()) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!(topic = msg.topic(), "MQTT payload error: {e}");
+                return;
+            },
+        };
+
+        if self.rule_topics.contains(msg.topic()) {
+            let event = rules::Event::Mqtt { topic: msg.topic(), payload };
+            let triggered = self.rules.evaluate(&event, &self.latest_states.lock().unwrap());
+            self.run_rule_actions(triggered).await;
+            return;
+        }
+
+        // lock messages were already turned into lock changes by the MQTT event loop
+        if self.lock_topics.contains(msg.topic()) {
+            return;
+        }
+
+        info!(topic = msg.topic(), payload, "MQTT command incoming");
+
+        if let Some((index, state_topic)) = self.schedules.topics.get(msg.topic()) {
+            let enabled = match payload {
+                "ON" => true,
+                "OFF" => false,
+                _ => {
+                    warn!(topic = msg.topic(), "MQTT payload error: expected ON or OFF");
+                    return;
+                },
+            };
+
+            if self.schedules.control.send((*index, enabled)).await.is_err() {
+                error!(topic = msg.topic(), "MQTT command error: scheduler is not running");
+                return;
+            }
+
+            merge_state(
+                &self.latest_states,
+                state_topic,
+                json!({ "enabled": enabled, "stale": false }),
+            );
+            self.outbox
+                .publish(&self.mqtt_client, [mqtt::switch_message(state_topic, enabled)])
+                .await;
+            return;
+        }
+
+        let (topic, action) = if let Some((cover_topic, button)) = self.covers.buttons.get(msg.topic()) {
+            (cover_topic.clone(), covers::CoverAction::Press(button.clone()))
+        } else if let Some(cover_topic) = self.covers.tilts.get(msg.topic()) {
+            match payload.parse::<u8>() {
+                Ok(position) => (cover_topic.clone(), covers::CoverCommand::tilt(position).into()),
+                Err(e) => {
+                    warn!(topic = msg.topic(), "MQTT payload error: {e}");
+                    return;
+                },
+            }
+        } else {
+            match payload.parse::<covers::CoverCommand>() {
+                Ok(cmd) => (msg.topic().to_owned(), cmd.into()),
+                Err(e) => {
+                    warn!(topic = msg.topic(), "MQTT payload error: {e}");
+                    return;
+                },
+            }
+        };
+
+        if !self.covers.queues.contains_key(&topic) {
+            warn!(topic, "MQTT command error: unknown cover");
+            return;
+        }
+
+        self.push_cover_action(&topic, action).await;
+    }
+
+    /// Queues the action of a known cover, commands discarded by its queue are reported as diagnostics.
+    async fn push_cover_action(&mut self, topic: &str, action: covers::CoverAction) {
+        merge_state(
+            &self.latest_states,
+            topic,
+            json!({ "last_command": action.to_string(), "stale": false }),
+        );
+
+        for discarded in self.covers.queues[topic].push(action) {
+            warn!(topic, "{discarded}");
+            let diagnostic = mqtt::diagnostic_message(&self.client_id, topic, &discarded.to_string());
+            self.outbox.publish(&self.mqtt_client, [diagnostic]).await;
+        }
+    }
+
+    /// Runs the rules triggered by the new latest state of the device with the topic.
+    async fn state_changed(&mut self, topic: &str) {
+        let triggered = {
+            let latest_states = self.latest_states.lock().unwrap();
+
+            match latest_states.get(topic) {
+                Some(state) => self
+                    .rules
+                    .evaluate(&rules::Event::State { topic, state }, &latest_states),
+                None => Vec::new(),
+            }
+        };
+
+        self.run_rule_actions(triggered).await;
+    }
+
+    async fn run_rule_actions(&mut self, triggered: Vec<(String, Vec<rules::Action>)>) {
+        for (rule, actions) in triggered {
+            info!(rule, "Running rule");
+
+            for action in actions {
+                match action {
+                    rules::Action::Cover { topic, command } => {
+                        self.push_cover_action(&topic, command.into()).await;
+                    },
+                    rules::Action::Gpio { output, active } => {
+                        if let Err(e) = self.rule_outputs[output].set_active(active) {
+                            error!(rule, "Unable to switch GPIO output: {e}");
+                            let diagnostic = mqtt::diagnostic_message(
+                                &self.client_id,
+                                &rule,
+                                &format!("Unable to switch GPIO output: {e}"),
+                            );
+                            self.outbox.publish(&self.mqtt_client, [diagnostic]).await;
+                        }
+                    },
+                    rules::Action::Publish { topic, payload, retain } => {
+                        self.outbox
+                            .publish(&self.mqtt_client, [mqtt::publish_message(&topic, &payload, retain)])
+                            .await;
+                    },
+                }
+            }
+        }
+    }
+}
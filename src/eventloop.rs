@@ -1,9 +1,53 @@
-use crate::{covers, sunspec};
-use std::{future::Future, sync::Arc};
+use crate::{
+    counter::{Counter, CounterState},
+    covers::{
+        self,
+        command_queue::{CommandQueue, DiscardedCommand},
+        position::{Movement, PositionTracker},
+    },
+    gpio::Pulses,
+    health::health,
+    metrics::metrics,
+    mqtt, onewire,
+    publish_filter::PublishFilter,
+    scheduler::{Schedule, ScheduleAction},
+    sunspec,
+};
+use chrono::Local;
+use std::{collections::HashMap, future::Future, sync::Arc};
 use tokio::{
+    select,
     sync::{mpsc, watch, Mutex},
     time::{self, Duration, Instant},
 };
+use tracing::{error, info, warn};
+
+const COVER_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+const SCHEDULER_TICK: Duration = Duration::from_secs(30);
+/// Schedules that are due for longer than this, e.g. because the clock jumped forward, are skipped.
+const SCHEDULE_MISSED_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Logs the commands discarded by the queue of a cover and reports them to the main loop as diagnostics.
+/// Returns `false` once the main loop is gone.
+async fn report_discarded(
+    topic: &str,
+    discarded: impl IntoIterator<Item = DiscardedCommand>,
+    tx: &mpsc::Sender<Message>,
+) -> bool {
+    for discarded in discarded {
+        warn!(topic, "{discarded}");
+
+        if tx
+            .send(Message::Diagnostic(topic.to_owned(), discarded.to_string()))
+            .await
+            .is_err()
+        {
+            return false;
+        }
+    }
+
+    true
+}
 
 pub struct Pause {
     delay: Duration,
@@ -29,89 +73,815 @@ impl Pause {
 pub enum Message {
     SunspecMeasurement(String, sunspec::varta::Measurements),
     MqttEvent(paho_mqtt::Message),
+    CoverCommand(String, covers::CoverCommand),
+    CoverStatus(String, covers::position::CoverStatus),
+    CoverTilt(String, u8),
+    CoverLock(String, usize, bool),
+    CounterState(String, CounterState),
+    OneWireTemperature(String, f64),
+    RuleInput(usize, bool),
+    RuleSchedule(usize),
+    Diagnostic(String, String),
+}
+
+/// Runs the event loop created by `make_loop` and restarts it with exponential backoff if it panics, returns or
+/// cannot be set up. Only a loop that returns after the main loop is gone has shut down and is not restarted.
+pub async fn supervise<F, Fut>(name: String, tx: mpsc::Sender<Message>, mut make_loop: F)
+where
+    F: FnMut() -> anyhow::Result<Fut>,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    const MIN_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let started = Instant::now();
+
+        let failure = match make_loop() {
+            Ok(fut) => match tokio::spawn(fut).await {
+                Ok(()) if tx.is_closed() => break,
+                Ok(()) => "event loop exited unexpectedly".to_owned(),
+                Err(e) => format!("event loop crashed: {e}"),
+            },
+            Err(e) => format!("unable to set up device: {e:#}"),
+        };
+
+        // a loop that ran for a while before failing is not part of a crash loop
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+
+        error!(topic = name, "{failure}, restarting in {backoff:?}");
+        health().restarting_within(&name, backoff + Duration::from_secs(10));
+
+        if tx.send(Message::Diagnostic(name.clone(), failure)).await.is_err() {
+            break;
+        }
+
+        time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// A cover lock that is driven by the messages on an MQTT topic.
+#[derive(Debug, Clone)]
+pub struct LockSubscription {
+    pub cover_topic: String,
+    pub index: usize,
+    /// payload that activates the lock, any other payload releases it
+    pub payload: String,
 }
 
 pub async fn mqtt_message_event_loop(
     mqtt_stream: paho_mqtt::AsyncReceiver<Option<paho_mqtt::Message>>,
+    lock_topics: HashMap<String, Vec<LockSubscription>>,
     tx: mpsc::Sender<Message>,
 ) {
-    loop {
+    const NAME: &str = "mqtt";
+    health().idle(NAME);
+
+    'events: loop {
         let Ok(event) = mqtt_stream.recv().await else {
             break;
         };
 
         match event {
             Some(event) => {
-                if tx.send(Message::MqttEvent(event)).await.is_err() {
-                    break;
+                // rules may be triggered by a lock topic as well, so the event is always passed on
+                let mut messages: Vec<_> = lock_topics
+                    .get(event.topic())
+                    .into_iter()
+                    .flatten()
+                    .map(|lock| {
+                        let active = event.payload() == lock.payload.as_bytes();
+                        Message::CoverLock(lock.cover_topic.clone(), lock.index, active)
+                    })
+                    .collect();
+                messages.push(Message::MqttEvent(event));
+
+                for message in messages {
+                    if tx.send(message).await.is_err() {
+                        break 'events;
+                    }
                 }
             },
-            None => println!("Lost connection to server"),
+            None => warn!("Lost connection to server"),
         }
     }
 
-    println!("Shutting down MQTT client");
+    info!("Shutting down MQTT client");
+    health().stopped(NAME);
+}
+
+pub async fn cover_event_loop(
+    topic: String,
+    group_gpio_pause: Arc<Mutex<Pause>>,
+    device_gpio_pause: Duration,
+    device: covers::Driver,
+    queue: Arc<CommandQueue>,
+    movement: Arc<watch::Sender<Movement>>,
+    tx: mpsc::Sender<Message>,
+) {
+    match device {
+        covers::Driver::Stateless(device) => {
+            stateless_cover_event_loop(topic, group_gpio_pause, device_gpio_pause, device, queue, movement, tx).await
+        },
+        covers::Driver::Relay(device) => relay_cover_event_loop(topic, device, queue, movement, tx).await,
+    }
 }
 
 pub fn stateless_cover_event_loop(
     topic: String,
     group_gpio_pause: Arc<Mutex<Pause>>,
     device_gpio_pause: Duration,
-    device: covers::stateless_gpio::Cover,
-) -> (watch::Sender<covers::CoverCommand>, impl Future<Output = ()>) {
-    let (tx, mut rx) = watch::channel(covers::CoverCommand::Stop);
+    mut device: covers::stateless_gpio::Cover,
+    queue: Arc<CommandQueue>,
+    movement: Arc<watch::Sender<Movement>>,
+    tx: mpsc::Sender<Message>,
+) -> impl Future<Output = ()> {
+    health().idle(&topic);
+
+    async move {
+        'commands: loop {
+            queue.ready().await;
+            health().expect_progress_within(&topic, COVER_COMMAND_TIMEOUT);
 
-    let fut = async move {
-        while rx.changed().await.is_ok() {
             let mut gtt = group_gpio_pause.lock().await;
             gtt.pause().await;
 
-            let cmd = *rx.borrow();
+            let cmd = loop {
+                match queue.pop() {
+                    Some(Ok(cmd)) => break Some(cmd),
+                    Some(Err(discarded)) => {
+                        if !report_discarded(&topic, [discarded], &tx).await {
+                            break 'commands;
+                        }
+                    },
+                    None => break None,
+                }
+            };
+
+            metrics()
+                .queue_depth
+                .with_label_values(&[&topic])
+                .set(queue.len() as i64);
+
+            if let Some(cmd) = cmd {
+                if cmd.is_tilt() && !device.has_tilt() {
+                    warn!(topic, "Cover has no tilt configured, ignoring {cmd}");
+                    health().idle(&topic);
+                    continue;
+                }
+
+                match device.perform(&cmd).await {
+                    Ok(()) => {
+                        metrics()
+                            .cover_presses
+                            .with_label_values(&[&topic, &cmd.to_string()])
+                            .inc();
+
+                        if let covers::CoverAction::Command(cmd) = cmd {
+                            movement.send_replace(match cmd {
+                                covers::CoverCommand::Open => Movement::Opening,
+                                covers::CoverCommand::Close => Movement::Closing,
+                                // tilting ends with the cover stopped
+                                _ => Movement::Stopped,
+                            });
+                        }
+
+                        if let Some(position) = device.tilt_position().filter(|_| cmd.is_tilt()) {
+                            if tx.send(Message::CoverTilt(topic.clone(), position)).await.is_err() {
+                                break 'commands;
+                            }
+                        }
+                    },
+                    Err(e) => error!(topic, "Unable to set gpio pin: {e}"),
+                }
 
-            if let Err(e) = device.issue_command(cmd).await {
-                eprintln!("Error unable to set gpio pin: {e}");
+                gtt.reset();
+                time::sleep(device_gpio_pause).await;
             }
 
-            gtt.reset();
-            time::sleep(device_gpio_pause).await;
+            health().idle(&topic);
         }
 
-        println!("Shutting down command listener for {topic}");
+        info!(topic, "Shutting down command listener");
+        health().stopped(&topic);
+    }
+}
+
+/// Fans the commands of a cover group out to its members in order. A member only gets the command once the
+/// previous one took it, so that members sharing the group pause issue it one after another.
+pub fn cover_group_event_loop(
+    topic: String,
+    queue: Arc<CommandQueue>,
+    members: Vec<(String, Arc<CommandQueue>)>,
+    tx: mpsc::Sender<Message>,
+) -> impl Future<Output = ()> {
+    health().idle(&topic);
+
+    async move {
+        'commands: loop {
+            queue.ready().await;
+
+            while let Some(next) = queue.pop() {
+                health().expect_progress_within(&topic, COVER_COMMAND_TIMEOUT);
+
+                let action = match next {
+                    Ok(action) => action,
+                    Err(discarded) => {
+                        if !report_discarded(&topic, [discarded], &tx).await {
+                            break 'commands;
+                        }
+
+                        continue;
+                    },
+                };
+
+                // buttons belong to a single cover, pressing them on every member would press unrelated buttons
+                if let covers::CoverAction::Press(button) = &action {
+                    warn!(topic, "Cover groups have no buttons, ignoring {button}");
+                    continue;
+                }
+
+                for (member_topic, member_queue) in &members {
+                    health().expect_progress_within(&topic, 2 * COVER_COMMAND_TIMEOUT);
+
+                    if !report_discarded(member_topic, member_queue.push(action.clone()), &tx).await {
+                        break 'commands;
+                    }
+
+                    // a stuck member, e.g. one that is being restarted, must not hold up the rest of the group
+                    if time::timeout(COVER_COMMAND_TIMEOUT, member_queue.drained())
+                        .await
+                        .is_err()
+                    {
+                        let message = format!("{member_topic} did not take {action} within {COVER_COMMAND_TIMEOUT:?}");
+                        warn!(topic, "{message}");
+
+                        if tx.send(Message::Diagnostic(topic.clone(), message)).await.is_err() {
+                            break 'commands;
+                        }
+                    }
+                }
+            }
+
+            health().idle(&topic);
+        }
+
+        info!(topic, "Shutting down cover group");
+        health().stopped(&topic);
+    }
+}
+
+/// Relay covers move until they are stopped or their travel time elapsed, they don't take part in the group pause
+/// as they don't share a remote with other covers.
+pub fn relay_cover_event_loop(
+    topic: String,
+    mut device: covers::relay::Cover,
+    queue: Arc<CommandQueue>,
+    movement: Arc<watch::Sender<Movement>>,
+    tx: mpsc::Sender<Message>,
+) -> impl Future<Output = ()> {
+    health().idle(&topic);
+
+    async move {
+        let mut travel_deadline = None;
+
+        'commands: loop {
+            select! {
+                _ = queue.ready() => {},
+                _ = time::sleep_until(travel_deadline.unwrap_or_else(Instant::now)), if travel_deadline.is_some() => {
+                    travel_deadline = None;
+
+                    if let Err(e) = device.stop() {
+                        error!(topic, "Unable to set gpio pin: {e}");
+                    }
+
+                    movement.send_replace(Movement::Stopped);
+                    continue;
+                },
+            }
+
+            health().expect_progress_within(&topic, COVER_COMMAND_TIMEOUT);
+
+            while let Some(next) = queue.pop() {
+                let action = match next {
+                    Ok(action) => action,
+                    Err(discarded) => {
+                        if !report_discarded(&topic, [discarded], &tx).await {
+                            break 'commands;
+                        }
+
+                        continue;
+                    },
+                };
+
+                if action.is_tilt() && !device.has_tilt() {
+                    warn!(topic, "Cover has no tilt configured, ignoring {action}");
+                    continue;
+                }
+
+                let cmd = match &action {
+                    covers::CoverAction::Command(cmd) => *cmd,
+                    covers::CoverAction::Press(button) => {
+                        warn!(topic, "Relay covers have no buttons, ignoring {button}");
+                        continue;
+                    },
+                };
+
+                let result = match cmd {
+                    covers::CoverCommand::Open => device.travel(covers::relay::Direction::Up).await,
+                    covers::CoverCommand::Close => device.travel(covers::relay::Direction::Down).await,
+                    covers::CoverCommand::Stop | covers::CoverCommand::TiltStop => device.stop(),
+                    covers::CoverCommand::TiltOpen => device.tilt_to(100).await,
+                    covers::CoverCommand::TiltClose => device.tilt_to(0).await,
+                    covers::CoverCommand::TiltPosition(position) => device.tilt_to(position).await,
+                };
+
+                travel_deadline = match cmd {
+                    covers::CoverCommand::Open | covers::CoverCommand::Close => {
+                        Some(Instant::now() + device.travel_time)
+                    },
+                    _ => None,
+                };
+
+                match result {
+                    Ok(()) => {
+                        metrics()
+                            .cover_presses
+                            .with_label_values(&[&topic, &action.to_string()])
+                            .inc();
+
+                        movement.send_replace(match device.moving() {
+                            Some(covers::relay::Direction::Up) => Movement::Opening,
+                            Some(covers::relay::Direction::Down) => Movement::Closing,
+                            None => Movement::Stopped,
+                        });
+
+                        if let Some(position) = device.tilt_position().filter(|_| cmd.is_tilt()) {
+                            if tx.send(Message::CoverTilt(topic.clone(), position)).await.is_err() {
+                                break 'commands;
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        error!(topic, "Unable to set gpio pin: {e}");
+
+                        // make sure nothing keeps moving in an unknown state
+                        if let Err(e) = device.stop() {
+                            error!(topic, "Unable to set gpio pin: {e}");
+                        }
+
+                        movement.send_replace(Movement::Stopped);
+                        travel_deadline = None;
+                    },
+                }
+            }
+
+            metrics()
+                .queue_depth
+                .with_label_values(&[&topic])
+                .set(queue.len() as i64);
+
+            health().idle(&topic);
+        }
+
+        info!(topic, "Shutting down command listener");
+        health().stopped(&topic);
+    }
+}
+
+/// Reports the level of a lock input, a lock whose input can't be read anymore stays active.
+pub async fn lock_input_event_loop(
+    topic: String,
+    index: usize,
+    mut input: watch::Receiver<bool>,
+    tx: mpsc::Sender<Message>,
+) {
+    loop {
+        let active = *input.borrow_and_update();
+
+        if tx.send(Message::CoverLock(topic.clone(), index, active)).await.is_err() {
+            return;
+        }
+
+        if input.changed().await.is_err() {
+            error!(topic, "Lock input is no longer monitored, keeping the lock active");
+            let _ = tx.send(Message::CoverLock(topic, index, true)).await;
+            return;
+        }
+    }
+}
+
+/// Forwards the level of the input line that triggers the rule with the index, starting with its current level.
+pub async fn rule_input_event_loop(rule: usize, mut input: watch::Receiver<bool>, tx: mpsc::Sender<Message>) {
+    loop {
+        let active = *input.borrow_and_update();
+
+        if tx.send(Message::RuleInput(rule, active)).await.is_err() {
+            return;
+        }
+
+        if input.changed().await.is_err() {
+            error!(rule, "Rule input is no longer monitored");
+            return;
+        }
+    }
+}
+
+/// Runs the actions of the schedules when their triggers fire. The clock is checked at least every
+/// `SCHEDULER_TICK`, so that the loop follows changes of the system time, e.g. once NTP synced after booting.
+/// Schedules missed because the clock jumped forward are skipped, if it jumped backwards they are rescheduled.
+pub async fn scheduler_event_loop(
+    schedules: Arc<Mutex<Vec<Schedule>>>,
+    control_rx: Arc<Mutex<mpsc::Receiver<(usize, bool)>>>,
+    tx: mpsc::Sender<Message>,
+) {
+    const NAME: &str = "scheduler";
+    health().idle(NAME);
+
+    let mut schedules = schedules.lock().await;
+    let mut control_rx = control_rx.lock().await;
+
+    let next_due = |schedules: &[Schedule], now| -> Vec<_> {
+        schedules
+            .iter()
+            .map(|schedule| schedule.trigger.next_after(now))
+            .collect()
     };
 
-    (tx, fut)
+    let mut last_now = Local::now();
+    let mut due = next_due(&schedules, last_now);
+
+    'schedules: loop {
+        let now = Local::now();
+
+        // the due times lie too far ahead once the clock was set back, e.g. after a wrong RTC time was corrected
+        if now < last_now {
+            info!("System time moved backwards, rescheduling");
+            due = next_due(&schedules, now);
+        }
+
+        last_now = now;
+
+        let wait = due
+            .iter()
+            .flatten()
+            .filter_map(|&at| (at - now).to_std().ok())
+            .min()
+            .unwrap_or(SCHEDULER_TICK)
+            .min(SCHEDULER_TICK);
+
+        select! {
+            _ = time::sleep(wait) => {
+                let now = Local::now();
+
+                if now < last_now {
+                    continue;
+                }
+
+                for (schedule, due) in schedules.iter().zip(&mut due) {
+                    let Some(at) = due.filter(|&at| at <= now) else {
+                        continue;
+                    };
+
+                    *due = schedule.trigger.next_after(now);
+
+                    if !schedule.enabled {
+                        continue;
+                    }
+
+                    if (now - at).to_std().is_ok_and(|late| late > SCHEDULE_MISSED_AFTER) {
+                        warn!(schedule = schedule.name, "Skipping schedule that was due at {at}");
+                        continue;
+                    }
+
+                    let messages = match &schedule.action {
+                        ScheduleAction::Cover { covers, command } => {
+                            info!(schedule = schedule.name, "Running schedule: {command}");
+                            covers.iter().map(|topic| Message::CoverCommand(topic.clone(), *command)).collect()
+                        },
+                        ScheduleAction::Rule(index) => vec![Message::RuleSchedule(*index)],
+                    };
+
+                    for message in messages {
+                        if tx.send(message).await.is_err() {
+                            break 'schedules;
+                        }
+                    }
+                }
+            },
+            control = control_rx.recv() => match control {
+                Some((index, enabled)) => {
+                    if let Some(schedule) = schedules.get_mut(index) {
+                        info!(schedule = schedule.name, enabled, "Switched schedule");
+                        schedule.enabled = enabled;
+                    }
+                },
+                None => break,
+            },
+        }
+    }
+
+    info!("Shutting down scheduler");
+    health().stopped(NAME);
+}
+
+/// Reports the state of the counter every `publish_interval`, its rate changes even while no pulses are counted.
+pub async fn counter_event_loop(
+    topic: String,
+    counter: Arc<Mutex<Counter>>,
+    pulses: watch::Receiver<Pulses>,
+    publish_interval: Duration,
+    tx: mpsc::Sender<Message>,
+) {
+    let mut counter = counter.lock().await;
+    let mut timer = time::interval(publish_interval);
+    let liveness_timeout = publish_interval + Duration::from_secs(10);
+
+    loop {
+        health().expect_progress_within(&topic, liveness_timeout);
+        timer.tick().await;
+
+        if pulses.has_changed().is_err() {
+            error!(topic, "Counter input is no longer monitored");
+            let _ = tx
+                .send(Message::Diagnostic(
+                    topic.clone(),
+                    "Counter input is no longer monitored".to_owned(),
+                ))
+                .await;
+            break;
+        }
+
+        let state = counter.state(&pulses.borrow());
+
+        if tx.send(Message::CounterState(topic.clone(), state)).await.is_err() {
+            break;
+        }
+    }
+
+    health().stopped(&topic);
+}
+
+/// Reads the sensors one after another, every read blocks for a conversion and runs on the blocking thread pool.
+/// A failing sensor is reported once as a diagnostic, until it can be read again.
+pub async fn onewire_event_loop(
+    sensors: Vec<(String, onewire::Sensor)>,
+    polling_interval: Duration,
+    tx: mpsc::Sender<Message>,
+) {
+    const NAME: &str = "onewire";
+
+    let mut timer = time::interval(polling_interval);
+    timer.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+
+    // a conversion takes up to 750 ms per sensor, the kernel retries failed reads
+    let liveness_timeout = polling_interval + Duration::from_secs(10 + 5 * sensors.len() as u64);
+    let mut failing = vec![false; sensors.len()];
+
+    loop {
+        health().expect_progress_within(NAME, liveness_timeout);
+        timer.tick().await;
+
+        for ((topic, sensor), failing) in sensors.iter().zip(&mut failing) {
+            let reading = {
+                let sensor = sensor.clone();
+                tokio::task::spawn_blocking(move || sensor.read_temperature()).await
+            };
+
+            let message = match reading {
+                Ok(Ok(temperature)) => {
+                    if std::mem::replace(failing, false) {
+                        info!(topic, "1-Wire sensor {} can be read again", sensor.id);
+                    }
+
+                    Message::OneWireTemperature(topic.clone(), temperature)
+                },
+                Ok(Err(e)) => {
+                    warn!(topic, "Unable to read 1-Wire sensor {}: {e}", sensor.id);
+
+                    if std::mem::replace(failing, true) {
+                        continue;
+                    }
+
+                    Message::Diagnostic(
+                        topic.clone(),
+                        format!("unable to read 1-Wire sensor {}: {e}", sensor.id),
+                    )
+                },
+                Err(e) => {
+                    error!(topic, "Reading 1-Wire sensor {} failed: {e}", sensor.id);
+                    continue;
+                },
+            };
+
+            if tx.send(message).await.is_err() {
+                health().stopped(NAME);
+                return;
+            }
+        }
+    }
+}
+
+async fn input_changed(input: &mut Option<watch::Receiver<bool>>) -> Result<bool, watch::error::RecvError> {
+    match input {
+        Some(input) => {
+            input.changed().await?;
+            Ok(*input.borrow_and_update())
+        },
+        None => std::future::pending().await,
+    }
+}
+
+pub fn cover_state_loop_name(topic: &str) -> String {
+    format!("{topic} state")
+}
+
+/// The end stop sensors of a cover, either one can be missing.
+#[derive(Clone)]
+pub struct EndStopSensors {
+    pub open: Option<watch::Receiver<bool>>,
+    pub closed: Option<watch::Receiver<bool>>,
+}
+
+/// Tracks the state and position of a cover from the movements of its driver and its end stops.
+pub fn cover_state_event_loop(
+    topic: String,
+    mut movement: watch::Receiver<Movement>,
+    sensors: EndStopSensors,
+    travel_time: Option<Duration>,
+    end_stop_timeout: Duration,
+    restored_position: Option<f64>,
+    tx: mpsc::Sender<Message>,
+) -> impl Future<Output = ()> {
+    let name = cover_state_loop_name(&topic);
+    health().idle(&name);
+
+    async move {
+        let EndStopSensors { open: mut open_sensor, closed: mut closed_sensor } = sensors;
+        let sensor_active =
+            |sensor: &Option<watch::Receiver<bool>>| sensor.as_ref().is_some_and(|sensor| *sensor.borrow());
+
+        let mut tracker = PositionTracker::new(travel_time);
+        let mut end_stop_deadline = None;
+        let mut last_status = None;
+        let mut position_timer = time::interval(Duration::from_secs(1));
+
+        if sensor_active(&open_sensor) || sensor_active(&closed_sensor) {
+            tracker.end_reached(sensor_active(&open_sensor), Instant::now());
+        } else if let Some(position) = restored_position {
+            // an end with a sensor that is not active has been left while the bridge was down
+            let left_end = (position >= 100.0 && open_sensor.is_some()) || (position <= 0.0 && closed_sensor.is_some());
+
+            if !left_end {
+                tracker.restore(position);
+            }
+        }
+
+        loop {
+            let status = tracker.status(
+                Instant::now(),
+                sensor_active(&open_sensor),
+                sensor_active(&closed_sensor),
+            );
+
+            if last_status.as_ref() != Some(&status) {
+                last_status = Some(status.clone());
+
+                if tx.send(Message::CoverStatus(topic.clone(), status)).await.is_err() {
+                    break;
+                }
+            }
+
+            select! {
+                changed = movement.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+
+                    let now = Instant::now();
+                    let current = *movement.borrow_and_update();
+                    tracker.set_movement(current, now);
+
+                    // the deadline also ends movements towards an end without a sensor, which can't be observed otherwise
+                    end_stop_deadline = match current {
+                        Movement::Opening if !sensor_active(&open_sensor) => Some((now + end_stop_timeout, open_sensor.is_some())),
+                        Movement::Closing if !sensor_active(&closed_sensor) => Some((now + end_stop_timeout, closed_sensor.is_some())),
+                        _ => None,
+                    };
+                },
+                changed = input_changed(&mut open_sensor) => match changed {
+                    Ok(true) => {
+                        tracker.end_reached(true, Instant::now());
+                        end_stop_deadline = None;
+                    },
+                    Ok(false) => {},
+                    Err(_) => {
+                        error!(topic, "Open end stop is no longer monitored");
+                        break;
+                    },
+                },
+                changed = input_changed(&mut closed_sensor) => match changed {
+                    Ok(true) => {
+                        tracker.end_reached(false, Instant::now());
+                        end_stop_deadline = None;
+                    },
+                    Ok(false) => {},
+                    Err(_) => {
+                        error!(topic, "Closed end stop is no longer monitored");
+                        break;
+                    },
+                },
+                _ = time::sleep_until(end_stop_deadline.map_or_else(Instant::now, |(deadline, _)| deadline)), if end_stop_deadline.is_some() => {
+                    let Some((_, monitored)) = end_stop_deadline.take() else {
+                        continue;
+                    };
+
+                    if !monitored {
+                        tracker.set_movement(Movement::Stopped, Instant::now());
+                        continue;
+                    }
+
+                    tracker.fault(Instant::now());
+
+                    let message = format!("cover did not reach its end stop within {end_stop_timeout:?}");
+                    warn!(topic, "{message}");
+
+                    if tx.send(Message::Diagnostic(topic.clone(), message)).await.is_err() {
+                        break;
+                    }
+                },
+                _ = position_timer.tick(), if tracker.is_moving() && travel_time.is_some() => {},
+            }
+        }
+
+        info!(topic, "Shutting down cover state tracker");
+        health().stopped(&name);
+    }
 }
 
 pub fn sunspec_event_loop(
     topic: String,
     device_polling_delay: Duration,
     mut device: sunspec::varta::ElementSunspecClient,
+    mut publish_filter: Option<PublishFilter>,
     tx: mpsc::Sender<Message>,
 ) -> impl Future<Output = ()> {
     let mut sensor_timer = time::interval(device_polling_delay);
     sensor_timer.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
 
+    // the worst case between two iterations is a full polling delay, two timed out requests and the retry delay
+    let liveness_timeout = device_polling_delay + Duration::from_secs(2 * 5 + 60 + 10);
+    health().expect_progress_within(&topic, liveness_timeout);
+
     async move {
         let mut last_measurement = None;
 
+        let mut should_publish = move |measurement| {
+            publish_filter
+                .as_mut()
+                .is_none_or(|filter| filter.should_publish(&mqtt::SunspecState::from(measurement)))
+        };
+
         loop {
+            health().expect_progress_within(&topic, liveness_timeout);
+
             sensor_timer.tick().await;
 
-            match time::timeout(Duration::from_secs(5), device.measure()).await {
+            let request_start = Instant::now();
+            let result = time::timeout(Duration::from_secs(5), device.measure()).await;
+            metrics()
+                .modbus_request_duration
+                .with_label_values(&[&topic])
+                .observe(request_start.elapsed().as_secs_f64());
+
+            match result {
                 Ok(Ok(measurement)) => {
                     last_measurement = Some(measurement);
 
-                    if tx
-                        .send(Message::SunspecMeasurement(topic.clone(), measurement))
-                        .await
-                        .is_err()
+                    if should_publish(measurement)
+                        && tx
+                            .send(Message::SunspecMeasurement(topic.clone(), measurement))
+                            .await
+                            .is_err()
                     {
                         break;
                     }
                 },
-                Ok(Err(e)) => eprintln!("Error unable to read from sunspec modbus: {e}"),
+                Ok(Err(e)) => {
+                    metrics().modbus_errors.with_label_values(&[&topic]).inc();
+                    error!(topic, "Unable to read from sunspec modbus: {e}");
+                },
                 Err(elapsed) => {
-                    eprintln!("Error modbus request for {topic} timed out after {elapsed}, trying again in 1 minute");
+                    metrics().modbus_timeouts.with_label_values(&[&topic]).inc();
+                    warn!(
+                        topic,
+                        "Modbus request timed out after {elapsed}, trying again in 1 minute"
+                    );
 
                     if let Some(last_measurement) = last_measurement.take() {
                         let placeholder = sunspec::varta::Measurements {
@@ -121,10 +891,11 @@ pub fn sunspec_event_loop(
                             ..last_measurement
                         };
 
-                        if tx
-                            .send(Message::SunspecMeasurement(topic.clone(), placeholder))
-                            .await
-                            .is_err()
+                        if should_publish(placeholder)
+                            && tx
+                                .send(Message::SunspecMeasurement(topic.clone(), placeholder))
+                                .await
+                                .is_err()
                         {
                             break;
                         }
@@ -135,6 +906,7 @@ pub fn sunspec_event_loop(
             }
         }
 
-        println!("Shutting down update timer for {topic}");
+        info!(topic, "Shutting down update timer");
+        health().stopped(&topic);
     }
 }
@@ -0,0 +1,309 @@
+use crate::config::{InputLineConfig, LineDrive, LineLevel, LineRef, OutputLineConfig};
+use gpio_cdev::{Chip, EventRequestFlags, LineDirection, LineHandle, LineRequestFlags};
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::sync::watch;
+use tracing::error;
+
+pub const CONSUMER: &str = "gpio2mqtt";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("GPIO line {0} is given as an offset, but no chip is configured")]
+    MissingChip(u32),
+    #[error("no GPIO line named {name:?}, candidates are: {candidates}")]
+    NotFound { name: String, candidates: String },
+    #[error("GPIO line {line} is claimed by both {first} and {second}")]
+    Conflict {
+        line: LineAddress,
+        first: String,
+        second: String,
+    },
+    #[error(transparent)]
+    Gpio(#[from] gpio_cdev::Error),
+}
+
+/// The chip and offset a line reference resolved to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LineAddress {
+    pub chip: PathBuf,
+    pub offset: u32,
+}
+
+impl Display for LineAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.chip.display(), self.offset)
+    }
+}
+
+/// Resolves a line reference, names are looked up on `chip` if given, otherwise on every chip of the system.
+pub fn resolve(chip: Option<&Path>, line: &LineRef) -> Result<LineAddress, Error> {
+    let name = match line {
+        LineRef::Offset(offset) => {
+            let chip = chip.ok_or(Error::MissingChip(*offset))?;
+            return Ok(LineAddress { chip: chip.to_owned(), offset: *offset });
+        },
+        LineRef::Name(name) => name,
+    };
+
+    let chips: Vec<_> = match chip {
+        Some(chip) => vec![Chip::new(chip)],
+        None => gpio_cdev::chips()?.collect(),
+    };
+
+    let mut named_lines = Vec::new();
+
+    for chip in chips {
+        let mut chip = chip?;
+
+        for offset in 0..chip.num_lines() {
+            let info = chip.get_line(offset)?.info()?;
+
+            if let Some(line_name) = info.name() {
+                if line_name == name {
+                    return Ok(LineAddress { chip: chip.path().to_owned(), offset });
+                }
+
+                named_lines.push(format!("{line_name} ({}:{offset})", chip.path().display()));
+            }
+        }
+    }
+
+    // only list the lines that look like a typo of the name, unless there are none
+    let lowercase_name = name.to_lowercase();
+    let similar: Vec<_> = named_lines
+        .iter()
+        .filter(|line| line.to_lowercase().contains(&lowercase_name))
+        .cloned()
+        .collect();
+
+    let candidates = if !similar.is_empty() { similar } else { named_lines };
+
+    Err(Error::NotFound {
+        name: name.clone(),
+        candidates: if candidates.is_empty() {
+            "none".to_owned()
+        } else {
+            candidates.join(", ")
+        },
+    })
+}
+
+/// Resolves every line of the config and makes sure no line is claimed twice, before any of them is requested.
+/// Every line is described by the name of its owner used in the error message, e.g. `Velux 1 up_pin`.
+pub fn check_conflicts<'a>(
+    lines: impl IntoIterator<Item = (String, Option<&'a Path>, &'a LineRef)>,
+) -> Result<(), Error> {
+    let mut claimed = HashMap::new();
+
+    for (owner, chip, line) in lines {
+        let address = resolve(chip, line)?;
+
+        if let Some(first) = claimed.insert(address.clone(), owner.clone()) {
+            return Err(Error::Conflict { line: address, first, second: owner });
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints every line of every GPIO chip, similar to `gpioinfo`.
+pub fn print_inventory() -> Result<(), Error> {
+    for chip in gpio_cdev::chips()? {
+        let mut chip = chip?;
+
+        println!(
+            "{} [{}] - {} lines:",
+            chip.path().display(),
+            chip.label(),
+            chip.num_lines()
+        );
+
+        for offset in 0..chip.num_lines() {
+            let info = chip.get_line(offset)?.info()?;
+
+            let mut flags = Vec::new();
+
+            if info.is_kernel() {
+                flags.push("used");
+            }
+            if info.is_open_drain() {
+                flags.push("open-drain");
+            }
+            if info.is_open_source() {
+                flags.push("open-source");
+            }
+
+            println!(
+                "\tline {offset:>3}: {name:<16} {consumer:<16} {direction:<6} {active:<11} {flags}",
+                name = info.name().map_or("unnamed".to_owned(), |name| format!("{name:?}")),
+                consumer = info
+                    .consumer()
+                    .map_or("unused".to_owned(), |consumer| format!("{consumer:?}")),
+                direction = match info.direction() {
+                    LineDirection::In => "input",
+                    LineDirection::Out => "output",
+                },
+                active = if info.is_active_low() {
+                    "active-low"
+                } else {
+                    "active-high"
+                },
+                flags = flags.join(" "),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Watches an input line from a background thread, the receiver always holds its current logical level.
+pub fn watch_input(chip: Option<&Path>, conf: &InputLineConfig) -> Result<watch::Receiver<bool>, Error> {
+    let address = resolve(chip, &conf.line)?;
+
+    let mut flags = LineRequestFlags::INPUT;
+
+    if conf.active_low {
+        flags |= LineRequestFlags::ACTIVE_LOW;
+    }
+
+    let mut events =
+        Chip::new(&address.chip)?
+            .get_line(address.offset)?
+            .events(flags, EventRequestFlags::BOTH_EDGES, CONSUMER)?;
+
+    let (tx, rx) = watch::channel(events.get_value()? != 0);
+
+    // reading events blocks, a plain thread doesn't keep the runtime from shutting down
+    std::thread::spawn(move || loop {
+        let level = events.get_event().and_then(|_| events.get_value());
+
+        match level {
+            Ok(level) => {
+                if tx.send(level != 0).is_err() {
+                    break;
+                }
+            },
+            Err(e) => {
+                error!(line = %address, "Unable to read gpio input: {e}");
+                break;
+            },
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Pulses counted on an input line since it was requested.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Pulses {
+    pub count: u64,
+    /// time between the last two pulses
+    pub interval: Option<Duration>,
+    /// when the last pulse was received
+    pub last: Option<Instant>,
+}
+
+/// Counts the pulses on an input line from a background thread, i.e. its changes to the active level. A pulse is
+/// ignored if it follows the previous one within `debounce`, the kernel timestamps of the edges are used for that.
+pub fn count_pulses(
+    chip: Option<&Path>,
+    conf: &InputLineConfig,
+    debounce: Duration,
+) -> Result<watch::Receiver<Pulses>, Error> {
+    let address = resolve(chip, &conf.line)?;
+
+    let mut flags = LineRequestFlags::INPUT;
+
+    if conf.active_low {
+        flags |= LineRequestFlags::ACTIVE_LOW;
+    }
+
+    // the kernel reports the edges of active low lines inverted, so the rising edge is always the active one
+    let mut events =
+        Chip::new(&address.chip)?
+            .get_line(address.offset)?
+            .events(flags, EventRequestFlags::RISING_EDGE, CONSUMER)?;
+
+    let (tx, rx) = watch::channel(Pulses::default());
+
+    std::thread::spawn(move || {
+        let mut last_timestamp = None;
+
+        loop {
+            let event = match events.get_event() {
+                Ok(event) => event,
+                Err(e) => {
+                    error!(line = %address, "Unable to read gpio input: {e}");
+                    break;
+                },
+            };
+
+            let timestamp = Duration::from_nanos(event.timestamp());
+            let interval = last_timestamp.map(|last_timestamp| timestamp.saturating_sub(last_timestamp));
+
+            if interval.is_some_and(|interval| interval < debounce) {
+                continue;
+            }
+
+            last_timestamp = Some(timestamp);
+
+            tx.send_modify(|pulses| {
+                pulses.count += 1;
+                pulses.interval = interval;
+                pulses.last = Some(Instant::now());
+            });
+
+            if tx.is_closed() {
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// An output line that rests at its configured idle level and is driven to the opposite level while active.
+pub struct OutputLine {
+    handle: LineHandle,
+    idle: u8,
+}
+
+impl OutputLine {
+    pub fn request(chip: Option<&Path>, conf: &OutputLineConfig) -> Result<Self, Error> {
+        let address = resolve(chip, &conf.line)?;
+
+        let mut flags = LineRequestFlags::OUTPUT;
+
+        if conf.active_low {
+            flags |= LineRequestFlags::ACTIVE_LOW;
+        }
+
+        match conf.drive {
+            LineDrive::PushPull => {},
+            LineDrive::OpenDrain => flags |= LineRequestFlags::OPEN_DRAIN,
+            LineDrive::OpenSource => flags |= LineRequestFlags::OPEN_SOURCE,
+        }
+
+        let idle = match conf.idle {
+            LineLevel::Low => 0,
+            LineLevel::High => 1,
+        };
+
+        // requesting the line with its idle level makes sure nothing is pressed while starting up
+        let handle = Chip::new(&address.chip)?
+            .get_line(address.offset)?
+            .request(flags, idle, CONSUMER)?;
+
+        Ok(Self { handle, idle })
+    }
+
+    pub fn set_active(&self, active: bool) -> Result<(), gpio_cdev::Error> {
+        self.handle.set_value(self.idle ^ u8::from(active))
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, OnceLock},
+};
+use tokio::time::{Duration, Instant};
+
+static HEALTH: OnceLock<Health> = OnceLock::new();
+
+pub fn health() -> &'static Health {
+    HEALTH.get_or_init(Health::default)
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Liveness {
+    /// Waiting for external events, e.g. a cover waiting for commands.
+    Idle,
+    /// Working, needs to report again before the deadline.
+    Busy {
+        deadline: Instant,
+    },
+    /// Crashed and waiting to be restarted by its supervisor before the deadline.
+    Restarting {
+        deadline: Instant,
+    },
+    Stopped,
+}
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopStatus {
+    Ok,
+    Restarting,
+    Stalled,
+    Stopped,
+}
+
+impl Liveness {
+    fn status(&self, now: Instant) -> LoopStatus {
+        match self {
+            Liveness::Idle => LoopStatus::Ok,
+            Liveness::Busy { deadline } if now <= *deadline => LoopStatus::Ok,
+            Liveness::Restarting { deadline } if now <= *deadline => LoopStatus::Restarting,
+            Liveness::Busy { .. } | Liveness::Restarting { .. } => LoopStatus::Stalled,
+            Liveness::Stopped => LoopStatus::Stopped,
+        }
+    }
+}
+
+/// Tracks whether the main loop and all spawned device loops are still making progress.
+#[derive(Default)]
+pub struct Health {
+    loops: Mutex<BTreeMap<String, Liveness>>,
+}
+
+#[derive(Debug)]
+pub struct HealthReport {
+    pub loops: BTreeMap<String, LoopStatus>,
+}
+
+impl HealthReport {
+    /// Every loop is either running or being restarted by its supervisor.
+    pub fn making_progress(&self) -> bool {
+        self.loops
+            .values()
+            .all(|status| matches!(status, LoopStatus::Ok | LoopStatus::Restarting))
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.loops.values().all(|&status| status == LoopStatus::Ok)
+    }
+}
+
+impl Health {
+    fn set(&self, name: &str, liveness: Liveness) {
+        self.loops.lock().unwrap().insert(name.to_owned(), liveness);
+    }
+
+    pub fn idle(&self, name: &str) {
+        self.set(name, Liveness::Idle);
+    }
+
+    pub fn expect_progress_within(&self, name: &str, timeout: Duration) {
+        self.set(name, Liveness::Busy { deadline: Instant::now() + timeout });
+    }
+
+    pub fn restarting_within(&self, name: &str, timeout: Duration) {
+        self.set(name, Liveness::Restarting { deadline: Instant::now() + timeout });
+    }
+
+    pub fn stopped(&self, name: &str) {
+        self.set(name, Liveness::Stopped);
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let now = Instant::now();
+
+        let loops = self
+            .loops
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, liveness)| (name.clone(), liveness.status(now)))
+            .collect();
+
+        HealthReport { loops }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{config::ApiToken, covers::CoverCommand, eventloop::Message, health::health, metrics::metrics};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::{net::TcpListener, sync::mpsc};
+use tracing::{error, info};
+
+/// Latest known state of every device, keyed by the topic the device is addressed by.
+pub type LatestStates = Arc<Mutex<HashMap<String, Value>>>;
+
+#[derive(Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Cover,
+    CoverGroup,
+    Sunspec,
+    Counter,
+    OneWire,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceInfo {
+    pub identifier: String,
+    pub name: String,
+    pub kind: DeviceKind,
+    #[serde(skip)]
+    pub topic: String,
+}
+
+#[derive(Serialize)]
+struct DeviceStatePayload<'a> {
+    #[serde(flatten)]
+    device: &'a DeviceInfo,
+    state: Option<Value>,
+}
+
+#[derive(Clone)]
+pub struct ApiState {
+    pub devices: Arc<Vec<DeviceInfo>>,
+    pub latest_states: LatestStates,
+    pub tx: mpsc::Sender<Message>,
+    pub mqtt_client: paho_mqtt::AsyncClient,
+}
+
+#[derive(Clone)]
+struct CommandState {
+    api: ApiState,
+    token: ApiToken,
+}
+
+/// Compares the bearer token in constant time, so that its prefix can't be guessed from response times.
+fn authorized(headers: &HeaderMap, token: &ApiToken) -> bool {
+    let Some(given) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    given.len() == token.0.len()
+        && given
+            .bytes()
+            .zip(token.0.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+impl ApiState {
+    fn device(&self, identifier: &str) -> Option<&DeviceInfo> {
+        self.devices.iter().find(|device| device.identifier == identifier)
+    }
+}
+
+async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics().encode(),
+    )
+}
+
+async fn get_healthz(State(state): State<ApiState>) -> impl IntoResponse {
+    let report = health().report();
+    let mqtt_connected = state.mqtt_client.is_connected();
+    let healthy = report.healthy() && mqtt_connected;
+
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(json!({ "healthy": healthy, "mqtt_connected": mqtt_connected, "loops": report.loops })),
+    )
+}
+
+async fn get_devices(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.devices.to_vec())
+}
+
+async fn get_device(State(state): State<ApiState>, Path(identifier): Path<String>) -> impl IntoResponse {
+    let Some(device) = state.device(&identifier) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let latest_state = state.latest_states.lock().unwrap().get(&device.topic).cloned();
+
+    Json(DeviceStatePayload { device, state: latest_state }).into_response()
+}
+
+async fn post_cover_command(
+    State(CommandState { api: state, token }): State<CommandState>,
+    headers: HeaderMap,
+    Path((identifier, command)): Path<(String, String)>,
+) -> impl IntoResponse {
+    if !authorized(&headers, &token) {
+        return (StatusCode::UNAUTHORIZED, "missing or invalid token").into_response();
+    }
+
+    let Some(device) = state
+        .device(&identifier)
+        .filter(|device| matches!(device.kind, DeviceKind::Cover | DeviceKind::CoverGroup))
+    else {
+        return (StatusCode::NOT_FOUND, "unknown cover").into_response();
+    };
+
+    let Ok(cmd) = command.to_uppercase().parse::<CoverCommand>() else {
+        return (StatusCode::BAD_REQUEST, "invalid cover command").into_response();
+    };
+
+    info!(topic = device.topic, "HTTP command incoming: {cmd}");
+
+    if state
+        .tx
+        .send(Message::CoverCommand(device.topic.clone(), cmd))
+        .await
+        .is_err()
+    {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+pub async fn serve(listener: TcpListener, api_state: ApiState) {
+    let router = Router::new()
+        .route("/metrics", get(get_metrics))
+        .route("/healthz", get(get_healthz))
+        .route("/devices", get(get_devices))
+        .route("/devices/:identifier", get(get_device))
+        .with_state(api_state);
+
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("HTTP server error: {e}");
+    }
+}
+
+/// Serves the command API, every request needs the configured bearer token.
+pub async fn serve_commands(listener: TcpListener, api_state: ApiState, token: ApiToken) {
+    let router = Router::new()
+        .route("/covers/:identifier/:command", post(post_cover_command))
+        .with_state(CommandState { api: api_state, token });
+
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("HTTP command server error: {e}");
+    }
+}
@@ -0,0 +1,22 @@
+use crate::config::{LogConfig, LogFormat};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_FILTER: &str = "info";
+
+/// Sets up the global logger. The `GPIO2MQTT_LOG` environment variable takes precedence over
+/// the configured filter, both use the `tracing_subscriber::EnvFilter` directive syntax
+/// (e.g. `info,gpio2mqtt::mqtt=debug`).
+pub fn init(conf: &LogConfig) -> anyhow::Result<()> {
+    let filter = match std::env::var("GPIO2MQTT_LOG") {
+        Ok(filter) => EnvFilter::try_new(filter)?,
+        Err(_) => EnvFilter::try_new(conf.filter.as_deref().unwrap_or(DEFAULT_FILTER))?,
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match conf.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    }
+    .map_err(|e| anyhow::anyhow!(e))
+}
@@ -1,20 +1,60 @@
 mod config;
+mod counter;
 mod covers;
+mod dispatch;
 mod eventloop;
+mod gpio;
+mod health;
+mod http;
+mod logging;
+mod metrics;
 mod mqtt;
+mod onewire;
+mod outbox;
+mod publish_filter;
+mod rules;
+mod scheduler;
+mod state_store;
 mod sunspec;
 
 use anyhow::{Context, Result};
 use paho_mqtt::{AsyncClient, ConnectOptionsBuilder, CreateOptionsBuilder, PersistenceType};
-use std::{collections::HashMap, fs::File, net::SocketAddr, sync::Arc};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    sync::Arc,
+};
 use tokio::{
+    net::TcpListener,
     select,
-    sync::{mpsc, Mutex},
+    signal::unix::{signal, Signal, SignalKind},
+    sync::{mpsc, watch, Mutex, Notify},
     time::Duration,
 };
+use tracing::{info, warn};
+
+const DEFAULT_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+const OUTBOX_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resolves on ctrl-c, or on SIGTERM, which is how systemd stops the service.
+async fn shutdown_requested(terminate: &mut Signal) {
+    select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
+    match std::env::args().skip(1).collect::<Vec<_>>().as_slice() {
+        [] => {},
+        [command, subcommand] if command == "gpio" && subcommand == "list" => {
+            return gpio::print_inventory().context("Failed to list GPIO lines");
+        },
+        _ => anyhow::bail!("Usage: gpio2mqtt [gpio list]"),
+    }
+
     let config_path = if cfg!(debug_assertions) {
         "./gpio2mqtt.yaml"
     } else {
@@ -26,7 +66,229 @@ async fn main() -> Result<()> {
     let config: config::Config =
         serde_yaml::from_reader(config).with_context(|| format!("Failed to parse config file {config_path:?}"))?;
 
-    let covers: Vec<(Duration, HashMap<_, _>)> = config
+    logging::init(&config.log).context("Failed to set up logging")?;
+
+    let onewire_sensors: Vec<_> = match &config.onewire {
+        Some(onewire_conf) => match onewire::discover(onewire_conf) {
+            Ok(sensors) => {
+                info!(sensors = sensors.len(), "Discovered 1-Wire sensors");
+                sensors
+                    .into_iter()
+                    .map(|sensor| {
+                        (
+                            mqtt::state_topic_for_dev_id(&config.client_id, &sensor.identifier),
+                            sensor,
+                        )
+                    })
+                    .collect()
+            },
+            Err(e) => {
+                warn!(path = %onewire_conf.sysfs_root.display(), "Unable to discover 1-Wire sensors: {e}");
+                Vec::new()
+            },
+        },
+        None => Vec::new(),
+    };
+
+    let mut sunspec_devices: Vec<_> = config
+        .sunspec
+        .iter()
+        .flatten()
+        .map(|sunspec_conf| {
+            let publish_filter = sunspec_conf
+                .publish
+                .clone()
+                .map(|publish_conf| publish_filter::PublishFilter::new(publish_conf, &mqtt::SunspecState::sample()))
+                .transpose()
+                .with_context(|| format!("Invalid publish config of {}", sunspec_conf.name))?;
+
+            Ok((
+                mqtt::state_topic_for_dev_id(&config.client_id, &sunspec_conf.device.identifier),
+                sunspec_conf.clone(),
+                sunspec::varta::ElementSunspecClient::from_config(sunspec_conf)?,
+                publish_filter,
+            ))
+        })
+        .collect::<Result<_>>()
+        .context("Failed to setup sunspec devices")?;
+
+    let sunspec_state_topics: HashMap<_, _> = config
+        .sunspec
+        .iter()
+        .flatten()
+        .map(|sunspec_conf| {
+            (
+                mqtt::state_topic_for_dev_id(&config.client_id, &sunspec_conf.device.identifier),
+                sunspec_conf.state_topics,
+            )
+        })
+        .collect();
+
+    let mut lock_topics: HashMap<_, Vec<_>> = HashMap::new();
+
+    for cover_conf in config
+        .covers
+        .iter()
+        .flatten()
+        .flat_map(|cover_group| &cover_group.devices)
+    {
+        for (index, lock) in cover_conf.locks.iter().enumerate() {
+            if let config::LockSource::Mqtt { topic, payload, .. } = &lock.source {
+                lock_topics
+                    .entry(topic.clone())
+                    .or_default()
+                    .push(eventloop::LockSubscription {
+                        cover_topic: mqtt::command_topic_for_dev_id(&config.client_id, &cover_conf.device.identifier),
+                        index,
+                        payload: payload.clone(),
+                    });
+            }
+        }
+    }
+
+    let devices: Vec<_> = config
+        .covers
+        .iter()
+        .flatten()
+        .flat_map(|cover_group| &cover_group.devices)
+        .map(|cover_conf| http::DeviceInfo {
+            identifier: cover_conf.device.identifier.0.clone(),
+            name: cover_conf.name.clone(),
+            kind: http::DeviceKind::Cover,
+            topic: mqtt::command_topic_for_dev_id(&config.client_id, &cover_conf.device.identifier),
+        })
+        .chain(config.covers.iter().flatten().filter_map(|cover_group| {
+            let identifier = cover_group.identifier.as_ref()?;
+
+            Some(http::DeviceInfo {
+                identifier: identifier.0.clone(),
+                name: cover_group.name()?,
+                kind: http::DeviceKind::CoverGroup,
+                topic: mqtt::command_topic_for_dev_id(&config.client_id, identifier),
+            })
+        }))
+        .chain(config.sunspec.iter().flatten().map(|sunspec_conf| http::DeviceInfo {
+            identifier: sunspec_conf.device.identifier.0.clone(),
+            name: sunspec_conf.name.clone(),
+            kind: http::DeviceKind::Sunspec,
+            topic: mqtt::state_topic_for_dev_id(&config.client_id, &sunspec_conf.device.identifier),
+        }))
+        .chain(onewire_sensors.iter().map(|(topic, sensor)| http::DeviceInfo {
+            identifier: sensor.identifier.0.clone(),
+            name: sensor.name.clone(),
+            kind: http::DeviceKind::OneWire,
+            topic: topic.clone(),
+        }))
+        .chain(config.counters.iter().flatten().map(|counter_conf| http::DeviceInfo {
+            identifier: counter_conf.device.identifier.0.clone(),
+            name: counter_conf.name.clone(),
+            kind: http::DeviceKind::Counter,
+            topic: mqtt::state_topic_for_dev_id(&config.client_id, &counter_conf.device.identifier),
+        }))
+        .collect();
+
+    let mut schedules: Vec<_> = match &config.scheduler {
+        Some(scheduler_conf) => scheduler_conf
+            .schedules
+            .iter()
+            .map(|schedule_conf| {
+                let covers = schedule_conf
+                    .covers
+                    .iter()
+                    .map(|identifier| {
+                        devices
+                            .iter()
+                            .find(|device| {
+                                device.identifier == identifier.0
+                                    && matches!(device.kind, http::DeviceKind::Cover | http::DeviceKind::CoverGroup)
+                            })
+                            .map(|device| device.topic.clone())
+                            .with_context(|| {
+                                format!(
+                                    "Schedule {} refers to unknown cover {}",
+                                    schedule_conf.name, identifier.0
+                                )
+                            })
+                    })
+                    .collect::<Result<_>>()?;
+
+                scheduler::Schedule::from_config(schedule_conf, scheduler_conf.location(), covers)
+                    .with_context(|| format!("Invalid schedule {}", schedule_conf.name))
+            })
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+
+    let rules =
+        rules::Rules::from_config(&config.rules, &devices, &config.client_id).context("Invalid rules config")?;
+    let rule_topics: HashSet<_> = rules.mqtt_topics().cloned().collect();
+
+    // scheduled rules follow the configured schedules, so the indices of the schedule switches stay valid
+    for (index, rule_conf) in config.rules.iter().enumerate() {
+        if let config::RuleTriggerConfig::Schedule(trigger) = &rule_conf.trigger {
+            let location = config.scheduler.as_ref().and_then(config::SchedulerConfig::location);
+
+            schedules.push(scheduler::Schedule {
+                name: rule_conf.name.clone(),
+                action: scheduler::ScheduleAction::Rule(index),
+                trigger: scheduler::Trigger::from_config(trigger, location)
+                    .with_context(|| format!("Invalid trigger of rule {}", rule_conf.name))?,
+                enabled: true,
+            });
+        }
+    }
+
+    // command topic to the index of the schedule and its state topic
+    let schedule_switches: HashMap<_, _> = config
+        .scheduler
+        .iter()
+        .flat_map(|scheduler_conf| scheduler_conf.schedules.iter().enumerate())
+        .map(|(index, schedule_conf)| {
+            (
+                mqtt::schedule_command_topic(&config.client_id, &schedule_conf.identifier),
+                (
+                    index,
+                    mqtt::schedule_state_topic(&config.client_id, &schedule_conf.identifier),
+                ),
+            )
+        })
+        .collect();
+
+    gpio::check_conflicts(
+        config
+            .covers
+            .iter()
+            .flatten()
+            .flat_map(|cover_group| &cover_group.devices)
+            .flat_map(|cover_conf| {
+                cover_conf
+                    .lines()
+                    .into_iter()
+                    .map(|(pin, line)| (format!("{} {pin}", cover_conf.name), cover_conf.chip.as_deref(), line))
+            })
+            .chain(config.counters.iter().flatten().map(|counter_conf| {
+                (
+                    format!("{} pin", counter_conf.name),
+                    counter_conf.chip.as_deref(),
+                    &counter_conf.pin.line,
+                )
+            }))
+            .chain(config.rules.iter().filter_map(|rule_conf| match &rule_conf.trigger {
+                config::RuleTriggerConfig::Input { input, chip, .. } => {
+                    Some((format!("rule {} input", rule_conf.name), chip.as_deref(), &input.line))
+                },
+                _ => None,
+            }))
+            .chain(
+                rules
+                    .outputs
+                    .iter()
+                    .map(|(chip, output)| ("rule output".to_owned(), chip.as_deref(), &output.line)),
+            ),
+    )
+    .context("Invalid GPIO config")?;
+
+    let cover_groups: Vec<(Duration, Option<config::Identifier>, Vec<_>)> = config
         .covers
         .iter()
         .flatten()
@@ -37,67 +299,77 @@ async fn main() -> Result<()> {
                 .map(|cover_conf| {
                     Ok((
                         mqtt::command_topic_for_dev_id(&config.client_id, &cover_conf.device.identifier),
-                        (
-                            Duration::from_millis(cover_conf.device_gpio_pause_ms.unwrap_or_default()),
-                            covers::stateless_gpio::Cover::from_chip_offsets(
-                                &cover_conf.chip,
-                                cover_conf.up_pin,
-                                cover_conf.down_pin,
-                                cover_conf.stop_pin,
-                            )?,
-                        ),
+                        cover_conf.clone(),
+                        covers::Driver::from_config(cover_conf)?,
                     ))
                 })
                 .collect::<Result<_>>()?;
 
             Ok((
                 Duration::from_millis(cover_group.group_gpio_pause_ms.unwrap_or_default()),
+                cover_group.identifier.clone(),
                 covers,
             ))
         })
         .collect::<Result<_>>()
         .context("Failed to set up GPIO pins")?;
 
-    let mut sunspec_devices: HashMap<_, _> = config
-        .sunspec
+    let rule_outputs: Vec<_> = rules
+        .outputs
+        .iter()
+        .map(|(chip, output)| gpio::OutputLine::request(chip.as_deref(), output))
+        .collect::<Result<_, _>>()
+        .context("Failed to set up rule GPIO outputs")?;
+
+    let counters: Vec<_> = config
+        .counters
         .iter()
         .flatten()
-        .map(|sunspec_conf| {
+        .map(|counter_conf| {
             Ok((
-                mqtt::state_topic_for_dev_id(&config.client_id, &sunspec_conf.device.identifier),
-                (
-                    Duration::from_millis(sunspec_conf.device_polling_delay_ms),
-                    sunspec::varta::ElementSunspecClient::new(SocketAddr::new(
-                        sunspec_conf.host.parse()?,
-                        sunspec_conf.host_port,
-                    )),
-                ),
+                mqtt::state_topic_for_dev_id(&config.client_id, &counter_conf.device.identifier),
+                counter_conf.clone(),
+                gpio::count_pulses(
+                    counter_conf.chip.as_deref(),
+                    &counter_conf.pin,
+                    Duration::from_millis(counter_conf.debounce_ms),
+                )?,
             ))
         })
         .collect::<Result<_>>()
-        .context("Failed to setup sunspec devices")?;
+        .context("Failed to set up counter inputs")?;
 
     let payloads = {
         let mut payloads = Vec::new();
 
+        if let Some(scheduler_conf) = &config.scheduler {
+            payloads.extend(mqtt::ConfigPayload::from_scheduler(&config.client_id, scheduler_conf));
+        }
+
         for cover_group in config.covers.into_iter().flatten() {
+            payloads.extend(mqtt::ConfigPayload::from_cover_group(&config.client_id, &cover_group));
+
             for cover_conf in cover_group.devices {
-                payloads.push(mqtt::ConfigPayload::from_cover_config(&config.client_id, cover_conf));
+                payloads.extend(mqtt::ConfigPayload::from_cover_config(&config.client_id, cover_conf));
             }
         }
 
-        for sunspec_conf in config.sunspec.into_iter().flatten() {
-            let (_, device) = sunspec_devices
-                .get_mut(&mqtt::state_topic_for_dev_id(
-                    &config.client_id,
-                    &sunspec_conf.device.identifier,
-                ))
-                .unwrap();
+        for (_, sensor) in &onewire_sensors {
+            payloads.push(mqtt::ConfigPayload::from_onewire(&config.client_id, sensor));
+        }
+
+        for (_, counter_conf, _) in &counters {
+            payloads.extend(mqtt::ConfigPayload::from_counter(
+                &config.client_id,
+                counter_conf.clone(),
+            ));
+        }
 
+        for (_, sunspec_conf, device, _) in &mut sunspec_devices {
             let specs = device.specifications().await.ok();
             payloads.extend(mqtt::ConfigPayload::from_sunspec(
                 &config.client_id,
-                sunspec_conf,
+                sunspec_conf.clone(),
                 specs.as_ref(),
             ));
         }
@@ -131,6 +403,17 @@ async fn main() -> Result<()> {
         .await
         .context("Failed to connect to MQTT broker")?;
 
+    let reconnected = Arc::new(Notify::new());
+
+    mqtt_client.set_connected_callback({
+        let reconnected = reconnected.clone();
+
+        move |_| {
+            metrics::metrics().mqtt_reconnects.inc();
+            reconnected.notify_one();
+        }
+    });
+
     mqtt::announce_online(&config.client_id, &mqtt_client)
         .await
         .context("Failed to announce online status")?;
@@ -139,75 +422,529 @@ async fn main() -> Result<()> {
         .await
         .context("Failed to register devices")?;
 
+    mqtt::subscribe(&mqtt_client, lock_topics.keys().chain(&rule_topics))
+        .await
+        .context("Failed to subscribe lock and rule topics")?;
+
+    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
+
+    let mut watchdog_usec = 0;
+    let watchdog_enabled = sd_notify::watchdog_enabled(false, &mut watchdog_usec);
+    let watchdog_interval = if watchdog_enabled {
+        Duration::from_micros(watchdog_usec) / 2
+    } else {
+        Duration::from_secs(10)
+    };
+
+    let mut watchdog_timer = tokio::time::interval(watchdog_interval);
+
+    let mut outbox = outbox::Outbox::new(config.outbox);
+    let mut outbox_retry_timer = tokio::time::interval(OUTBOX_RETRY_INTERVAL);
+
     let (tx, mut rx) = mpsc::channel(1);
 
-    for (topic, (polling_delay, device)) in sunspec_devices {
-        tokio::spawn(eventloop::sunspec_event_loop(topic, polling_delay, device, tx.clone()));
+    let latest_states = http::LatestStates::default();
+
+    let mut state_store = config.state_store.map(state_store::StateStore::new);
+    let mut state_save_timer = tokio::time::interval(
+        state_store
+            .as_ref()
+            .map_or(DEFAULT_STATE_SAVE_INTERVAL, state_store::StateStore::save_interval),
+    );
+    let restored_states = state_store
+        .as_mut()
+        .map(state_store::StateStore::restore)
+        .unwrap_or_default();
+    let restored_cover_status = |topic: &str| {
+        let state = restored_states.get(topic)?;
+        serde_json::from_value::<covers::position::CoverStatus>(state.clone()).ok()
+    };
+
+    if let Some(http_conf) = &config.http {
+        let listener = TcpListener::bind(http_conf.listen)
+            .await
+            .with_context(|| format!("Failed to bind HTTP listener to {}", http_conf.listen))?;
+
+        let api_state = http::ApiState {
+            devices: Arc::new(devices),
+            latest_states: latest_states.clone(),
+            tx: tx.clone(),
+            mqtt_client: mqtt_client.clone(),
+        };
+
+        if let Some(commands_conf) = &http_conf.commands {
+            let commands_listener = TcpListener::bind(commands_conf.listen)
+                .await
+                .with_context(|| format!("Failed to bind HTTP command listener to {}", commands_conf.listen))?;
+
+            tokio::spawn(http::serve_commands(
+                commands_listener,
+                api_state.clone(),
+                commands_conf.token.clone(),
+            ));
+        }
+
+        tokio::spawn(http::serve(listener, api_state));
     }
 
-    let cover_channels = {
-        let mut cover_channels = HashMap::new();
+    for (topic, sunspec_conf, device, publish_filter) in sunspec_devices {
+        let mut device = Some(device);
+        let tx = tx.clone();
+
+        tokio::spawn(eventloop::supervise(topic.clone(), tx.clone(), move || {
+            let device = match device.take() {
+                Some(device) => device,
+                None => sunspec::varta::ElementSunspecClient::from_config(&sunspec_conf)?,
+            };
+
+            Ok(eventloop::sunspec_event_loop(
+                topic.clone(),
+                Duration::from_millis(sunspec_conf.device_polling_delay_ms),
+                device,
+                publish_filter.clone(),
+                tx.clone(),
+            ))
+        }));
+    }
 
-        for (group_delay, group) in covers {
+    let (
+        cover_queues,
+        cover_buttons,
+        cover_state_topics,
+        cover_tilts,
+        cover_tilt_topics,
+        cover_lock_topics,
+        cover_group_members,
+    ) = {
+        let mut cover_queues = HashMap::new();
+        let mut cover_group_members = Vec::new();
+        let mut cover_buttons = HashMap::new();
+        let mut cover_state_topics = HashMap::new();
+        let mut cover_tilts = HashMap::new();
+        let mut cover_tilt_topics = HashMap::new();
+        let mut cover_lock_topics = HashMap::new();
+
+        for (group_delay, group_identifier, group) in cover_groups {
             let group_gpio_pause = Arc::new(Mutex::new(eventloop::Pause::new(group_delay)));
+            let mut members = Vec::new();
+            let mut group_has_tilt = false;
+
+            for (topic, cover_conf, device) in group {
+                let queue = Arc::new(covers::command_queue::CommandQueue::from_config(&cover_conf));
+                cover_queues.insert(topic.clone(), queue.clone());
+                members.push((topic.clone(), queue.clone()));
+
+                for button in cover_conf.buttons() {
+                    let button_topic = mqtt::button_command_topic_for_dev_id(
+                        &config.client_id,
+                        &cover_conf.device.identifier,
+                        &button.identifier,
+                    );
+
+                    cover_buttons.insert(button_topic, (topic.clone(), button.identifier.0.clone()));
+                }
+
+                if cover_conf.tilt.is_some() {
+                    group_has_tilt = true;
+                    let dev_id = &cover_conf.device.identifier;
+
+                    cover_tilts.insert(
+                        mqtt::tilt_command_topic_for_dev_id(&config.client_id, dev_id),
+                        topic.clone(),
+                    );
+                    cover_tilt_topics.insert(topic.clone(), mqtt::tilt_topic_for_dev_id(&config.client_id, dev_id));
+                }
+
+                if !cover_conf.locks.is_empty() {
+                    let lock_topic = mqtt::lock_topic_for_dev_id(&config.client_id, &cover_conf.device.identifier);
+                    cover_lock_topics.insert(topic.clone(), lock_topic);
+                }
+
+                for (index, lock) in cover_conf.locks.iter().enumerate() {
+                    if let config::LockSource::Pin { pin } = &lock.source {
+                        let input = gpio::watch_input(cover_conf.chip.as_deref(), pin)?;
+                        let topic = topic.clone();
+                        let tx = tx.clone();
+
+                        tokio::spawn(eventloop::supervise(
+                            format!("{topic} lock {}", lock.name),
+                            tx.clone(),
+                            move || {
+                                Ok(eventloop::lock_input_event_loop(
+                                    topic.clone(),
+                                    index,
+                                    input.clone(),
+                                    tx.clone(),
+                                ))
+                            },
+                        ));
+                    }
+                }
+
+                let (movement_tx, movement_rx) = watch::channel(covers::position::Movement::Stopped);
+                let movement = Arc::new(movement_tx);
+
+                if cover_conf.reports_state() {
+                    let chip = cover_conf.chip.as_deref();
+                    let sensors = eventloop::EndStopSensors {
+                        open: cover_conf
+                            .open_sensor_pin
+                            .as_ref()
+                            .map(|line| gpio::watch_input(chip, line))
+                            .transpose()?,
+                        closed: cover_conf
+                            .closed_sensor_pin
+                            .as_ref()
+                            .map(|line| gpio::watch_input(chip, line))
+                            .transpose()?,
+                    };
+
+                    let state_topic = mqtt::state_topic_for_dev_id(&config.client_id, &cover_conf.device.identifier);
+                    cover_state_topics.insert(topic.clone(), state_topic);
+
+                    let travel_time = cover_conf.travel_time();
+                    let end_stop_timeout = cover_conf.end_stop_timeout();
+                    // only the first run continues from the restored position, later runs have no position yet
+                    let mut restored_position =
+                        restored_cover_status(&topic).and_then(|status| status.known_position());
+                    let topic = topic.clone();
+                    let tx = tx.clone();
+
+                    tokio::spawn(eventloop::supervise(
+                        eventloop::cover_state_loop_name(&topic),
+                        tx.clone(),
+                        move || {
+                            Ok(eventloop::cover_state_event_loop(
+                                topic.clone(),
+                                movement_rx.clone(),
+                                sensors.clone(),
+                                travel_time,
+                                end_stop_timeout,
+                                restored_position.take(),
+                                tx.clone(),
+                            ))
+                        },
+                    ));
+                }
+
+                let group_gpio_pause = group_gpio_pause.clone();
+                let mut device = Some(device);
+                let tx = tx.clone();
+
+                tokio::spawn(eventloop::supervise(topic.clone(), tx.clone(), move || {
+                    let device = match device.take() {
+                        Some(device) => device,
+                        None => covers::Driver::from_config(&cover_conf)?,
+                    };
+
+                    Ok(eventloop::cover_event_loop(
+                        topic.clone(),
+                        group_gpio_pause.clone(),
+                        Duration::from_millis(cover_conf.device_gpio_pause_ms.unwrap_or_default()),
+                        device,
+                        queue.clone(),
+                        movement.clone(),
+                        tx.clone(),
+                    ))
+                }));
+            }
+
+            if let Some(group_identifier) = group_identifier {
+                let topic = mqtt::command_topic_for_dev_id(&config.client_id, &group_identifier);
+                let queue = Arc::new(covers::command_queue::CommandQueue::new(
+                    config::CommandPolicy::LatestWins,
+                    None,
+                    covers::lock::Locks::from_config(&[]),
+                ));
+                cover_queues.insert(topic.clone(), queue.clone());
+
+                let member_topics: Vec<_> = members.iter().map(|(member_topic, _)| member_topic.clone()).collect();
+
+                if member_topics
+                    .iter()
+                    .any(|member_topic| cover_state_topics.contains_key(member_topic))
+                {
+                    cover_state_topics.insert(
+                        topic.clone(),
+                        mqtt::state_topic_for_dev_id(&config.client_id, &group_identifier),
+                    );
+                }
 
-            for (topic, (device_gpio_pause, device)) in group {
-                let (tx, fut) = eventloop::stateless_cover_event_loop(
-                    topic.clone(),
-                    group_gpio_pause.clone(),
-                    device_gpio_pause,
-                    device,
-                );
+                if group_has_tilt {
+                    let tilt_command_topic = mqtt::tilt_command_topic_for_dev_id(&config.client_id, &group_identifier);
+                    cover_tilts.insert(tilt_command_topic, topic.clone());
+                }
 
-                cover_channels.insert(topic, tx);
-                tokio::spawn(fut);
+                cover_group_members.push((topic.clone(), member_topics));
+
+                let tx = tx.clone();
+
+                tokio::spawn(eventloop::supervise(topic.clone(), tx.clone(), move || {
+                    Ok(eventloop::cover_group_event_loop(
+                        topic.clone(),
+                        queue.clone(),
+                        members.clone(),
+                        tx.clone(),
+                    ))
+                }));
             }
         }
 
-        cover_channels
+        (
+            cover_queues,
+            cover_buttons,
+            cover_state_topics,
+            cover_tilts,
+            cover_tilt_topics,
+            cover_lock_topics,
+            cover_group_members,
+        )
     };
 
-    tokio::spawn(eventloop::mqtt_message_event_loop(mqtt_stream, tx));
+    for (index, rule_conf) in config.rules.iter().enumerate() {
+        if let config::RuleTriggerConfig::Input { input, chip, .. } = &rule_conf.trigger {
+            let input = gpio::watch_input(chip.as_deref(), input)
+                .with_context(|| format!("Failed to set up input of rule {}", rule_conf.name))?;
+            let tx = tx.clone();
+
+            tokio::spawn(eventloop::supervise(
+                format!("rule {} input", rule_conf.name),
+                tx.clone(),
+                move || Ok(eventloop::rule_input_event_loop(index, input.clone(), tx.clone())),
+            ));
+        }
+    }
+
+    for (topic, lock_topic) in &cover_lock_topics {
+        let status = cover_queues[topic].lock_status();
+        let status = mqtt::Timestamped::now(&status);
+        let messages = mqtt::state_messages(lock_topic, &status, config::StateTopics::Json);
+        outbox.publish(&mqtt_client, messages).await;
+    }
+
+    // the states are republished as stale, until the devices report their current states
+    let mut cover_states = HashMap::new();
+    let group_topics: HashSet<_> = cover_group_members.iter().map(|(group_topic, _)| group_topic).collect();
+
+    for (topic, state) in restored_states.clone() {
+        if let Some(&layout) = sunspec_state_topics.get(&topic) {
+            outbox
+                .publish(&mqtt_client, mqtt::state_messages(&topic, &state, layout))
+                .await;
+        } else if let Some((index, _)) = schedule_switches
+            .values()
+            .find(|(_, state_topic)| *state_topic == topic)
+        {
+            if let Some(enabled) = state["enabled"].as_bool() {
+                schedules[*index].enabled = enabled;
+            }
+        } else if cover_queues.contains_key(&topic) {
+            let is_group = group_topics.contains(&topic);
+
+            if let (Some(status), false) = (restored_cover_status(&topic), is_group) {
+                cover_states.insert(topic.clone(), status.state);
+            }
+
+            if let Some(state_topic) = cover_state_topics.get(&topic) {
+                let fields: &[_] = if is_group {
+                    &["state", "timestamp", "stale"]
+                } else {
+                    &["state", "position", "fault", "timestamp", "stale"]
+                };
+                let status: serde_json::Map<_, _> = fields
+                    .iter()
+                    .filter_map(|&field| Some((field.to_owned(), state.get(field)?.clone())))
+                    .collect();
+
+                if status.contains_key("state") {
+                    let messages = mqtt::state_messages(state_topic, &status, config::StateTopics::Json);
+                    outbox.publish(&mqtt_client, messages).await;
+                }
+            }
+        } else if counters.iter().any(|(counter_topic, ..)| *counter_topic == topic)
+            || onewire_sensors.iter().any(|(sensor_topic, _)| *sensor_topic == topic)
+        {
+            outbox
+                .publish(
+                    &mqtt_client,
+                    mqtt::state_messages(&topic, &state, config::StateTopics::Json),
+                )
+                .await;
+        } else {
+            // the device has been removed from the config
+            continue;
+        }
+
+        latest_states.lock().unwrap().insert(topic, state);
+    }
+
+    let schedule_control = {
+        for &(index, ref state_topic) in schedule_switches.values() {
+            let enabled = schedules[index].enabled;
+            dispatch::merge_state(
+                &latest_states,
+                state_topic,
+                json!({ "enabled": enabled, "stale": false }),
+            );
+            outbox
+                .publish(&mqtt_client, [mqtt::switch_message(state_topic, enabled)])
+                .await;
+        }
+
+        let (schedule_control, schedule_control_rx) = mpsc::channel(16);
+
+        if !schedules.is_empty() {
+            // shared with restarted loops, so that schedules stay switched and switching keeps working
+            let schedules = Arc::new(Mutex::new(schedules));
+            let schedule_control_rx = Arc::new(Mutex::new(schedule_control_rx));
+            let tx = tx.clone();
+
+            tokio::spawn(eventloop::supervise("scheduler".to_owned(), tx.clone(), move || {
+                Ok(eventloop::scheduler_event_loop(
+                    schedules.clone(),
+                    schedule_control_rx.clone(),
+                    tx.clone(),
+                ))
+            }));
+        }
+
+        schedule_control
+    };
+
+    // counting continues from the pulses of the restored state
+    for (topic, counter_conf, pulses) in counters {
+        let offset = latest_states
+            .lock()
+            .unwrap()
+            .get(&topic)
+            .and_then(|state| state["pulses"].as_u64())
+            .unwrap_or_default();
+
+        info!(topic, offset, "Starting counter");
+
+        // shared with restarted loops, so that the count never goes backwards
+        let counter = Arc::new(Mutex::new(counter::Counter::from_config(&counter_conf, offset)));
+        let publish_interval = Duration::from_millis(counter_conf.publish_interval_ms);
+        let tx = tx.clone();
+
+        tokio::spawn(eventloop::supervise(topic.clone(), tx.clone(), move || {
+            Ok(eventloop::counter_event_loop(
+                topic.clone(),
+                counter.clone(),
+                pulses.clone(),
+                publish_interval,
+                tx.clone(),
+            ))
+        }));
+    }
+
+    if let (Some(onewire_conf), false) = (&config.onewire, onewire_sensors.is_empty()) {
+        let polling_interval = Duration::from_millis(onewire_conf.polling_interval_ms);
+        let tx = tx.clone();
+
+        tokio::spawn(eventloop::supervise("onewire".to_owned(), tx.clone(), move || {
+            Ok(eventloop::onewire_event_loop(
+                onewire_sensors.clone(),
+                polling_interval,
+                tx.clone(),
+            ))
+        }));
+    }
+
+    {
+        let lock_topics = lock_topics.clone();
+
+        tokio::spawn(eventloop::supervise("mqtt".to_owned(), tx.clone(), move || {
+            Ok(eventloop::mqtt_message_event_loop(
+                mqtt_stream.clone(),
+                lock_topics.clone(),
+                tx.clone(),
+            ))
+        }));
+    }
+
+    let mut dispatcher = dispatch::Dispatcher {
+        client_id: config.client_id.clone(),
+        mqtt_client: mqtt_client.clone(),
+        outbox,
+        latest_states: latest_states.clone(),
+        covers: dispatch::Covers {
+            queues: cover_queues,
+            buttons: cover_buttons,
+            state_topics: cover_state_topics,
+            tilts: cover_tilts,
+            tilt_topics: cover_tilt_topics,
+            lock_topics: cover_lock_topics,
+            group_members: cover_group_members,
+            states: cover_states,
+        },
+        schedules: dispatch::ScheduleSwitches { topics: schedule_switches, control: schedule_control },
+        sunspec_state_topics,
+        rules,
+        rule_outputs,
+        rule_topics: rule_topics.clone(),
+        lock_topics: lock_topics.keys().cloned().collect(),
+    };
+
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM")?;
 
     loop {
+        metrics::metrics()
+            .queue_depth
+            .with_label_values(&["events"])
+            .set(rx.len() as i64);
+
         select! {
-            _ = tokio::signal::ctrl_c() => {
-                let _ = mqtt::announce_offline(&config.client_id, &mqtt_client).await;
-                break Ok(());
+            _ = watchdog_timer.tick() => {
+                health::health().expect_progress_within("main", 2 * watchdog_interval);
+
+                if watchdog_enabled && health::health().report().making_progress() {
+                    let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]);
+                }
             },
-            event = rx.recv() => match event.unwrap() {
-                eventloop::Message::SunspecMeasurement(topic, measurement) => {
-                    mqtt::publish_state(&mqtt_client, topic, &mqtt::SunspecState::from(measurement))
-                        .await
-                        .context("Unable to publish state")?;
-                },
-                eventloop::Message::MqttEvent(msg) => {
-                    let payload = match std::str::from_utf8(msg.payload()) {
-                        Ok(payload) => payload,
-                        Err(e) => {
-                            eprintln!("MQTT payload error: {e}");
-                            continue;
-                        }
-                    };
+            // the backlog is published in batches, other events are handled in between
+            _ = tokio::task::yield_now(), if dispatcher.outbox.has_backlog() => {
+                dispatcher.outbox.flush(&mqtt_client).await;
+            },
+            _ = outbox_retry_timer.tick(), if !dispatcher.outbox.is_empty() || dispatcher.outbox.is_dirty() => {
+                dispatcher.outbox.flush(&mqtt_client).await;
+                dispatcher.outbox.persist();
+            },
+            _ = reconnected.notified() => {
+                info!("Reconnected to MQTT broker");
 
-                    println!("MQTT command incoming: topic '{}' payload '{}'", msg.topic(), payload);
+                // the broker might have restarted and lost all retained messages and subscriptions
+                if let Err(e) = mqtt::announce_online(&config.client_id, &mqtt_client).await {
+                    warn!("Unable to announce online status: {e:#}");
+                }
 
-                    let Some(chan) = cover_channels.get(msg.topic()) else {
-                        eprintln!("MQTT command error: unknown cover at {}", msg.topic());
-                        continue;
-                    };
+                if let Err(e) = mqtt::register_devices(&mqtt_client, &payloads).await {
+                    warn!("Unable to register devices: {e:#}");
+                }
 
-                    let cmd = match payload.parse() {
-                        Ok(cmd) => cmd,
-                        Err(e) => {
-                            eprintln!("MQTT payload error: {e}");
-                            continue;
-                        },
-                    };
+                if let Err(e) = mqtt::subscribe(&mqtt_client, lock_topics.keys().chain(&rule_topics)).await {
+                    warn!("Unable to subscribe lock and rule topics: {e:#}");
+                }
 
-                    chan.send(cmd).unwrap();
-                },
-            }
+                dispatcher.outbox.flush(&mqtt_client).await;
+            },
+            _ = state_save_timer.tick(), if state_store.is_some() => {
+                let states = latest_states.lock().unwrap().clone();
+                state_store.as_mut().unwrap().save(&states);
+            },
+            _ = shutdown_requested(&mut terminate) => {
+                info!("Shutting down");
+
+                if let Some(state_store) = &mut state_store {
+                    let states = latest_states.lock().unwrap().clone();
+                    state_store.save(&states);
+                }
+
+                dispatcher.outbox.persist();
+                let _ = mqtt::announce_offline(&config.client_id, &mqtt_client).await;
+                break Ok(());
+            },
+            event = rx.recv() => dispatcher.handle(event.unwrap()).await,
         }
     }
 }
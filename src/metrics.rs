@@ -0,0 +1,153 @@
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+pub struct Metrics {
+    registry: Registry,
+    pub mqtt_publishes: IntCounterVec,
+    pub mqtt_reconnects: IntCounter,
+    pub modbus_request_duration: HistogramVec,
+    pub modbus_errors: IntCounterVec,
+    pub modbus_timeouts: IntCounterVec,
+    pub cover_presses: IntCounterVec,
+    pub queue_depth: IntGaugeVec,
+    pub sunspec_measurements: GaugeVec,
+    pub sunspec_state: IntGaugeVec,
+    last_sunspec_states: Mutex<HashMap<String, String>>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("gpio2mqtt".to_owned()), None).unwrap();
+
+        let mqtt_publishes = IntCounterVec::new(
+            Opts::new("mqtt_publishes_total", "MQTT publishes by result"),
+            &["result"],
+        )
+        .unwrap();
+
+        let mqtt_reconnects = IntCounter::new(
+            "mqtt_reconnects_total",
+            "Reconnects to the MQTT broker after a lost connection",
+        )
+        .unwrap();
+
+        let modbus_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "modbus_request_duration_seconds",
+                "Duration of modbus measurement requests",
+            )
+            .buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+            &["device"],
+        )
+        .unwrap();
+
+        let modbus_errors =
+            IntCounterVec::new(Opts::new("modbus_errors_total", "Failed modbus requests"), &["device"]).unwrap();
+
+        let modbus_timeouts = IntCounterVec::new(
+            Opts::new("modbus_timeouts_total", "Timed out modbus requests"),
+            &["device"],
+        )
+        .unwrap();
+
+        let cover_presses = IntCounterVec::new(
+            Opts::new("cover_presses_total", "Simulated GPIO button presses per cover"),
+            &["device", "command"],
+        )
+        .unwrap();
+
+        let queue_depth = IntGaugeVec::new(
+            Opts::new("queue_depth", "Number of queued messages in the event loop channels"),
+            &["channel"],
+        )
+        .unwrap();
+
+        let sunspec_measurements = GaugeVec::new(
+            Opts::new("sunspec_measurement", "Latest numeric sunspec measurements"),
+            &["device", "field"],
+        )
+        .unwrap();
+
+        let sunspec_state = IntGaugeVec::new(
+            Opts::new("sunspec_state", "Latest sunspec device state, 1 for the current state"),
+            &["device", "state"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(mqtt_publishes.clone())).unwrap();
+        registry.register(Box::new(mqtt_reconnects.clone())).unwrap();
+        registry.register(Box::new(modbus_request_duration.clone())).unwrap();
+        registry.register(Box::new(modbus_errors.clone())).unwrap();
+        registry.register(Box::new(modbus_timeouts.clone())).unwrap();
+        registry.register(Box::new(cover_presses.clone())).unwrap();
+        registry.register(Box::new(queue_depth.clone())).unwrap();
+        registry.register(Box::new(sunspec_measurements.clone())).unwrap();
+        registry.register(Box::new(sunspec_state.clone())).unwrap();
+
+        Self {
+            registry,
+            mqtt_publishes,
+            mqtt_reconnects,
+            modbus_request_duration,
+            modbus_errors,
+            modbus_timeouts,
+            cover_presses,
+            queue_depth,
+            sunspec_measurements,
+            sunspec_state,
+            last_sunspec_states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record_publish<T, E>(&self, result: &Result<T, E>) {
+        let result = if result.is_ok() { "ok" } else { "error" };
+        self.mqtt_publishes.with_label_values(&[result]).inc();
+    }
+
+    pub fn record_sunspec_state(&self, device: &str, state: &impl Serialize) {
+        let Ok(Value::Object(fields)) = serde_json::to_value(state) else {
+            return;
+        };
+
+        for (field, value) in fields {
+            match value {
+                Value::Number(value) => {
+                    if let Some(value) = value.as_f64() {
+                        self.sunspec_measurements
+                            .with_label_values(&[device, &field])
+                            .set(value);
+                    }
+                },
+                Value::String(value) if field == "state" => {
+                    let mut last_states = self.last_sunspec_states.lock().unwrap();
+
+                    if let Some(last_state) = last_states.insert(device.to_owned(), value.clone()) {
+                        let _ = self.sunspec_state.remove_label_values(&[device, &last_state]);
+                    }
+
+                    self.sunspec_state.with_label_values(&[device, &value]).set(1);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
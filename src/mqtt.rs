@@ -1,12 +1,18 @@
 use paho_mqtt::{AsyncClient, Message};
 use serde::Serialize;
-use std::fmt::Write;
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
 
 use crate::{
-    config, sunspec,
+    config,
+    metrics::metrics,
+    onewire, sunspec,
     sunspec::{
         varta::{BatteryPower, GridPower, Measurements, State},
-        Percentage, WattHours, Watts,
+        Percentage, SignedWatts, WattHours, Watts,
     },
 };
 
@@ -20,40 +26,60 @@ fn mqtt_avail_topic(client_id: &str) -> String {
     format!("{client_id}/bridge/state")
 }
 
+fn mqtt_diagnostics_topic(client_id: &str) -> String {
+    format!("{client_id}/bridge/diagnostics")
+}
+
+pub async fn publish(client: &AsyncClient, msg: Message) -> paho_mqtt::Result<()> {
+    debug!(topic = msg.topic(), payload = %msg.payload_str(), "MQTT publish");
+
+    let result = client.publish(msg).await;
+    metrics().record_publish(&result);
+    result
+}
+
 pub async fn register_devices(client: &AsyncClient, payloads: &[ConfigPayload]) -> anyhow::Result<()> {
     for payload in payloads {
-        println!(
-            "MQTT publish: topic '{}' payload '{}'",
-            payload.config_topic,
-            serde_json::to_string(payload).unwrap()
-        );
-
-        let c = client.clone();
-        let p = payload.clone();
-
-        c.publish(Message::new_retained(
-            &p.config_topic,
-            serde_json::to_vec(&p).unwrap(),
-            QOS_AT_LEAST_ONCE,
-        ))
+        publish(
+            client,
+            Message::new_retained(
+                &payload.config_topic,
+                serde_json::to_vec(payload).unwrap(),
+                QOS_AT_LEAST_ONCE,
+            ),
+        )
         .await?;
 
-        if let DeviceSpecificConfig::Cover { command_topic, .. } = &payload.specific {
+        if let DeviceSpecificConfig::Cover { command_topic, .. }
+        | DeviceSpecificConfig::Button { command_topic, .. }
+        | DeviceSpecificConfig::Switch { command_topic, .. } = &payload.specific
+        {
             client.subscribe(command_topic, QOS_AT_LEAST_ONCE).await?;
         }
+
+        if let DeviceSpecificConfig::Cover { tilt_command_topic: Some(tilt_command_topic), .. } = &payload.specific {
+            client.subscribe(tilt_command_topic, QOS_AT_LEAST_ONCE).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Subscribes topics that are not the command topic of a device, e.g. the sources of cover locks.
+pub async fn subscribe(client: &AsyncClient, topics: impl IntoIterator<Item = &String>) -> anyhow::Result<()> {
+    for topic in topics {
+        client.subscribe(topic, QOS_AT_LEAST_ONCE).await?;
     }
 
     Ok(())
 }
 
 pub async fn announce_online(client_id: &str, client: &AsyncClient) -> anyhow::Result<()> {
-    client
-        .publish(Message::new_retained(
-            mqtt_avail_topic(client_id),
-            b"online".to_owned(),
-            QOS_AT_LEAST_ONCE,
-        ))
-        .await?;
+    publish(
+        client,
+        Message::new_retained(mqtt_avail_topic(client_id), b"online".to_owned(), QOS_AT_LEAST_ONCE),
+    )
+    .await?;
     Ok(())
 }
 
@@ -62,42 +88,133 @@ pub fn offline_message(client_id: &str) -> Message {
 }
 
 pub async fn announce_offline(client_id: &str, client: &AsyncClient) -> anyhow::Result<()> {
-    client.publish(offline_message(client_id)).await?;
+    publish(client, offline_message(client_id)).await?;
     Ok(())
 }
 
-pub async fn publish_state<S: Into<String>>(
-    client: &AsyncClient,
-    topic: S,
-    payload: &impl Serialize,
-) -> anyhow::Result<()> {
-    let topic = topic.into();
-
-    println!(
-        "MQTT publish topic: '{}' payload: '{}'",
-        topic,
-        serde_json::to_string(payload).unwrap()
-    );
-
-    client
-        .publish(Message::new(
+pub fn state_messages(topic: &str, payload: &impl Serialize, layout: config::StateTopics) -> Vec<Message> {
+    match layout {
+        config::StateTopics::Json => vec![Message::new(
             topic,
             serde_json::to_vec(payload).unwrap(),
             QOS_AT_LEAST_ONCE,
-        ))
-        .await?;
+        )],
+        config::StateTopics::PerField => {
+            let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(payload) else {
+                return Vec::new();
+            };
+
+            fields
+                .into_iter()
+                .map(|(field, value)| {
+                    let value = match value {
+                        serde_json::Value::String(s) => s,
+                        value => value.to_string(),
+                    };
+
+                    Message::new(state_field_topic(topic, &field), value, QOS_AT_LEAST_ONCE)
+                })
+                .collect()
+        },
+    }
+}
 
-    Ok(())
+/// Payload with the time it was created embedded, so that messages replayed after a broker outage
+/// can be told apart from current ones.
+#[derive(Serialize, Debug)]
+pub struct Timestamped<'a, T> {
+    #[serde(flatten)]
+    pub payload: &'a T,
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl<'a, T> Timestamped<'a, T> {
+    pub fn now(payload: &'a T) -> Self {
+        Self {
+            payload,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DiagnosticPayload<'a> {
+    source: &'a str,
+    message: &'a str,
+}
+
+pub fn diagnostic_message(client_id: &str, source: &str, message: &str) -> Message {
+    Message::new(
+        mqtt_diagnostics_topic(client_id),
+        serde_json::to_vec(&Timestamped::now(&DiagnosticPayload { source, message })).unwrap(),
+        QOS_AT_LEAST_ONCE,
+    )
+}
+
+pub fn switch_message(state_topic: &str, on: bool) -> Message {
+    Message::new(state_topic, if on { "ON" } else { "OFF" }, QOS_AT_LEAST_ONCE)
+}
+
+pub fn publish_message(topic: &str, payload: &str, retain: bool) -> Message {
+    if retain {
+        Message::new_retained(topic, payload, QOS_AT_LEAST_ONCE)
+    } else {
+        Message::new(topic, payload, QOS_AT_LEAST_ONCE)
+    }
+}
+
+pub fn tilt_message(tilt_topic: &str, position: u8) -> Message {
+    Message::new(tilt_topic, position.to_string(), QOS_AT_LEAST_ONCE)
 }
 
 pub fn command_topic_for_dev_id(client_id: &str, dev_id: &config::Identifier) -> String {
     format!("{client_id}/{dev_id}/set", dev_id = dev_id.0)
 }
 
+pub fn button_command_topic_for_dev_id(
+    client_id: &str,
+    dev_id: &config::Identifier,
+    button_id: &config::Identifier,
+) -> String {
+    format!(
+        "{client_id}/{dev_id}/{button_id}/set",
+        dev_id = dev_id.0,
+        button_id = button_id.0
+    )
+}
+
+pub fn tilt_command_topic_for_dev_id(client_id: &str, dev_id: &config::Identifier) -> String {
+    format!("{client_id}/{dev_id}/tilt/set", dev_id = dev_id.0)
+}
+
+pub fn tilt_topic_for_dev_id(client_id: &str, dev_id: &config::Identifier) -> String {
+    format!("{client_id}/{dev_id}/tilt", dev_id = dev_id.0)
+}
+
+pub fn lock_topic_for_dev_id(client_id: &str, dev_id: &config::Identifier) -> String {
+    format!("{client_id}/{dev_id}/lock", dev_id = dev_id.0)
+}
+
+pub fn schedule_command_topic(client_id: &str, schedule_id: &config::Identifier) -> String {
+    format!("{client_id}/schedule/{schedule_id}/set", schedule_id = schedule_id.0)
+}
+
+pub fn schedule_state_topic(client_id: &str, schedule_id: &config::Identifier) -> String {
+    format!("{client_id}/schedule/{schedule_id}", schedule_id = schedule_id.0)
+}
+
 pub fn state_topic_for_dev_id(client_id: &str, dev_id: &config::Identifier) -> String {
     format!("{client_id}/{dev_id}/state", dev_id = dev_id.0)
 }
 
+pub fn state_field_topic(state_topic: &str, field: &str) -> String {
+    format!("{state_topic}/{field}")
+}
+
 #[derive(Serialize, Debug, Clone)]
 pub struct AvailabilityPayload {
     topic: String,
@@ -172,12 +289,23 @@ pub enum DeviceClass {
     VolatileOrganicCompounds,
     Voltage,
     Volume,
+    VolumeFlowRate,
     VolumeStorage,
     Water,
     Weight,
     WindSpeed,
 }
 
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum BinarySensorDeviceClass {
+    Lock,
+    Problem,
+}
+
+/// The `*_net_power` fields follow the Home Assistant energy convention:
+/// positive battery power is discharging, positive grid power is consumption.
+/// `stale` is only set for states restored at startup that were not yet refreshed by the device.
 #[derive(Serialize, Debug)]
 pub struct SunspecState {
     state: State,
@@ -187,8 +315,25 @@ pub struct SunspecState {
     battery_active_discharge_power: Watts,
     battery_apparent_charge_power: VoltAmps,
     battery_apparent_discharge_power: VoltAmps,
+    battery_net_power: SignedWatts,
     grid_backfeed_power: Watts,
     grid_consumption_power: Watts,
+    grid_net_power: SignedWatts,
+    stale: bool,
+}
+
+impl SunspecState {
+    /// A state with every field set, to check configured field names against.
+    pub fn sample() -> Self {
+        Self::from(Measurements {
+            state: State::Ready,
+            state_of_charge: 0,
+            total_charge_energy: 0,
+            active_battery_power: Some(0),
+            apparent_battery_power: Some(0),
+            grid_power: Some(0),
+        })
+    }
 }
 
 impl From<Measurements> for SunspecState {
@@ -197,30 +342,33 @@ impl From<Measurements> for SunspecState {
             state: value.state,
             state_of_charge: value.state_of_charge,
             total_charge_energy: value.total_charge_energy,
-            battery_active_charge_power: match value.active_battery_power {
+            battery_active_charge_power: match value.active_battery_power() {
                 Some(BatteryPower::Charge(w)) => w,
                 _ => 0,
             },
-            battery_active_discharge_power: match value.active_battery_power {
+            battery_active_discharge_power: match value.active_battery_power() {
                 Some(BatteryPower::Discharge(w)) => w,
                 _ => 0,
             },
-            battery_apparent_charge_power: match value.apparent_battery_power {
+            battery_apparent_charge_power: match value.apparent_battery_power() {
                 Some(BatteryPower::Charge(va)) => va,
                 _ => 0,
             },
-            battery_apparent_discharge_power: match value.apparent_battery_power {
+            battery_apparent_discharge_power: match value.apparent_battery_power() {
                 Some(BatteryPower::Discharge(va)) => va,
                 _ => 0,
             },
-            grid_backfeed_power: match value.grid_power {
+            battery_net_power: value.active_battery_power.unwrap_or_default().saturating_neg(),
+            grid_backfeed_power: match value.grid_power() {
                 Some(GridPower::Backfeed(w)) => w,
                 _ => 0,
             },
-            grid_consumption_power: match value.grid_power {
+            grid_consumption_power: match value.grid_power() {
                 Some(GridPower::Consumption(w)) => w,
                 _ => 0,
             },
+            grid_net_power: value.grid_power.unwrap_or_default().saturating_neg(),
+            stale: false,
         }
     }
 }
@@ -230,6 +378,26 @@ impl From<Measurements> for SunspecState {
 pub enum DeviceSpecificConfig {
     Cover {
         command_topic: String,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        state_topic: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        value_template: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        position_topic: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        position_template: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tilt_command_topic: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tilt_status_topic: Option<String>,
+    },
+    Button {
+        command_topic: String,
+    },
+    Switch {
+        command_topic: String,
+        state_topic: String,
     },
     Sensor {
         state_topic: String,
@@ -243,6 +411,23 @@ pub enum DeviceSpecificConfig {
         #[serde(skip_serializing_if = "Option::is_none")]
         device_class: Option<DeviceClass>,
     },
+    BinarySensor {
+        state_topic: String,
+        value_template: String,
+        device_class: BinarySensorDeviceClass,
+    },
+}
+
+impl DeviceSpecificConfig {
+    fn component(&self) -> &'static str {
+        match self {
+            DeviceSpecificConfig::Cover { .. } => "cover",
+            DeviceSpecificConfig::Button { .. } => "button",
+            DeviceSpecificConfig::Switch { .. } => "switch",
+            DeviceSpecificConfig::Sensor { .. } => "sensor",
+            DeviceSpecificConfig::BinarySensor { .. } => "binary_sensor",
+        }
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -278,23 +463,235 @@ impl ConfigPayload {
         s
     }
 
-    pub fn from_cover_config(client_id: &str, conf: config::CoverConfig) -> Self {
+    pub fn from_cover_config(client_id: &str, conf: config::CoverConfig) -> Vec<Self> {
+        let buttons = conf.buttons().to_vec();
+        let has_end_stops = conf.has_end_stops();
+        let reports_state = conf.reports_state();
+        let has_position = conf.travel_time().is_some();
+        let has_tilt = conf.tilt.is_some();
+        let has_locks = !conf.locks.is_empty();
         let dev_id = conf.device.identifier;
         let unique_id = format!("{client_id}_{dev_id}", dev_id = dev_id.0);
 
-        Self {
+        let device = DevicePayload {
+            name: conf.name.clone(),
+            identifiers: vec![unique_id.clone()],
+            manufacturer: conf.device.manufacturer,
+            model: conf.device.model,
+            sw_version: None,
+        };
+
+        let buttons = buttons.into_iter().map(|button| ConfigPayload {
+            config_topic: format!(
+                "{MQTT_DISCOVERY_TOPIC}/button/{unique_id}/{button_id}/config",
+                button_id = button.identifier.0
+            ),
+            unique_id: format!("{unique_id}_{button_id}", button_id = button.identifier.0),
+            specific: DeviceSpecificConfig::Button {
+                command_topic: button_command_topic_for_dev_id(client_id, &dev_id, &button.identifier),
+            },
+            availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+            device: device.clone(),
+            name: format!("{} {}", conf.name, button.name),
+        });
+
+        let state_topic = reports_state.then(|| state_topic_for_dev_id(client_id, &dev_id));
+
+        let cover = Self {
             config_topic: format!("{MQTT_DISCOVERY_TOPIC}/cover/{unique_id}/config"),
             unique_id: unique_id.clone(),
-            specific: DeviceSpecificConfig::Cover { command_topic: command_topic_for_dev_id(client_id, &dev_id) },
+            specific: DeviceSpecificConfig::Cover {
+                command_topic: command_topic_for_dev_id(client_id, &dev_id),
+                state_topic: state_topic.clone(),
+                value_template: state_topic.as_ref().map(|_| "{{ value_json.state }}".to_owned()),
+                position_topic: state_topic.clone().filter(|_| has_position),
+                position_template: has_position.then(|| "{{ value_json.position }}".to_owned()),
+                tilt_command_topic: has_tilt.then(|| tilt_command_topic_for_dev_id(client_id, &dev_id)),
+                tilt_status_topic: has_tilt.then(|| tilt_topic_for_dev_id(client_id, &dev_id)),
+            },
+            availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+            device: device.clone(),
+            name: conf.name.clone(),
+        };
+
+        let fault = state_topic.filter(|_| has_end_stops).map(|state_topic| Self {
+            config_topic: format!("{MQTT_DISCOVERY_TOPIC}/binary_sensor/{unique_id}/fault/config"),
+            unique_id: format!("{unique_id}_fault"),
+            specific: DeviceSpecificConfig::BinarySensor {
+                state_topic,
+                value_template: "{{ 'ON' if value_json.fault else 'OFF' }}".to_owned(),
+                device_class: BinarySensorDeviceClass::Problem,
+            },
+            availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+            device: device.clone(),
+            name: format!("{} fault", conf.name),
+        });
+
+        // the lock device class is on while unlocked
+        let lock = has_locks.then(|| Self {
+            config_topic: format!("{MQTT_DISCOVERY_TOPIC}/binary_sensor/{unique_id}/lock/config"),
+            unique_id: format!("{unique_id}_lock"),
+            specific: DeviceSpecificConfig::BinarySensor {
+                state_topic: lock_topic_for_dev_id(client_id, &dev_id),
+                value_template: "{{ 'OFF' if value_json.locked else 'ON' }}".to_owned(),
+                device_class: BinarySensorDeviceClass::Lock,
+            },
+            availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+            device: device.clone(),
+            name: format!("{} lock", conf.name),
+        });
+
+        std::iter::once(cover).chain(fault).chain(lock).chain(buttons).collect()
+    }
+
+    pub fn from_cover_group(client_id: &str, conf: &config::CoverGroup) -> Option<Self> {
+        let dev_id = conf.identifier.as_ref()?;
+        let name = conf.name()?;
+        let unique_id = format!("{client_id}_{dev_id}", dev_id = dev_id.0);
+        let state_topic = conf
+            .devices
+            .iter()
+            .any(config::CoverConfig::reports_state)
+            .then(|| state_topic_for_dev_id(client_id, dev_id));
+        let has_tilt = conf.devices.iter().any(|device| device.tilt.is_some());
+
+        Some(Self {
+            config_topic: format!("{MQTT_DISCOVERY_TOPIC}/cover/{unique_id}/config"),
+            unique_id: unique_id.clone(),
+            specific: DeviceSpecificConfig::Cover {
+                command_topic: command_topic_for_dev_id(client_id, dev_id),
+                value_template: state_topic
+                    .as_ref()
+                    .map(|_| "{{ 'stopped' if value_json.state == 'mixed' else value_json.state }}".to_owned()),
+                state_topic,
+                position_topic: None,
+                position_template: None,
+                tilt_command_topic: has_tilt.then(|| tilt_command_topic_for_dev_id(client_id, dev_id)),
+                tilt_status_topic: None,
+            },
             availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
             device: DevicePayload {
-                name: conf.name.clone(),
+                name: name.clone(),
                 identifiers: vec![unique_id],
-                manufacturer: conf.device.manufacturer,
-                model: conf.device.model,
+                manufacturer: None,
+                model: None,
+                sw_version: None,
+            },
+            name,
+        })
+    }
+
+    /// Switches that enable and disable the schedules, grouped into a scheduler device.
+    pub fn from_scheduler(client_id: &str, conf: &config::SchedulerConfig) -> Vec<Self> {
+        let device = DevicePayload {
+            name: format!("{client_id} scheduler"),
+            identifiers: vec![format!("{client_id}_scheduler")],
+            manufacturer: None,
+            model: None,
+            sw_version: None,
+        };
+
+        conf.schedules
+            .iter()
+            .map(|schedule| {
+                let unique_id = format!(
+                    "{client_id}_schedule_{schedule_id}",
+                    schedule_id = schedule.identifier.0
+                );
+
+                Self {
+                    config_topic: format!("{MQTT_DISCOVERY_TOPIC}/switch/{unique_id}/config"),
+                    unique_id,
+                    specific: DeviceSpecificConfig::Switch {
+                        command_topic: schedule_command_topic(client_id, &schedule.identifier),
+                        state_topic: schedule_state_topic(client_id, &schedule.identifier),
+                    },
+                    availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+                    device: device.clone(),
+                    name: schedule.name.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// A total and a rate sensor, the device classes follow the kind of meter.
+    pub fn from_counter(client_id: &str, conf: config::CounterConfig) -> Vec<Self> {
+        let dev_id = conf.device.identifier;
+        let unique_id = format!("{client_id}_{dev_id}", dev_id = dev_id.0);
+        let state_topic = state_topic_for_dev_id(client_id, &dev_id);
+
+        let (total_class, rate_class) = match conf.meter {
+            config::MeterKind::Energy => (DeviceClass::Energy, DeviceClass::Power),
+            config::MeterKind::Water => (DeviceClass::Water, DeviceClass::VolumeFlowRate),
+            config::MeterKind::Gas => (DeviceClass::Gas, DeviceClass::VolumeFlowRate),
+        };
+
+        let sensors = [
+            (
+                "total",
+                DeviceSpecificConfig::Sensor {
+                    state_topic: state_topic.clone(),
+                    device_class: Some(total_class),
+                    state_class: Some(StateClass::TotalIncreasing),
+                    unit_of_measurement: Some(conf.meter.total_unit().to_owned()),
+                    value_template: Some("{{ value_json.total }}".to_owned()),
+                },
+            ),
+            (
+                "rate",
+                DeviceSpecificConfig::Sensor {
+                    state_topic,
+                    device_class: Some(rate_class),
+                    state_class: Some(StateClass::Measurement),
+                    unit_of_measurement: Some(conf.meter.rate_unit().to_owned()),
+                    value_template: Some("{{ value_json.rate }}".to_owned()),
+                },
+            ),
+        ];
+
+        let device = DevicePayload {
+            name: conf.name.clone(),
+            identifiers: vec![unique_id.clone()],
+            manufacturer: conf.device.manufacturer,
+            model: conf.device.model,
+            sw_version: conf.device.sw_version,
+        };
+
+        sensors
+            .into_iter()
+            .map(|(sensor_name, sensor)| Self {
+                config_topic: format!("{MQTT_DISCOVERY_TOPIC}/sensor/{unique_id}/{sensor_name}/config"),
+                unique_id: format!("{unique_id}_{sensor_name}"),
+                availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+                device: device.clone(),
+                name: format!("{} {sensor_name}", conf.name),
+                specific: sensor,
+            })
+            .collect()
+    }
+
+    pub fn from_onewire(client_id: &str, sensor: &onewire::Sensor) -> Self {
+        let unique_id = format!("{client_id}_{dev_id}", dev_id = sensor.identifier.0);
+
+        Self {
+            config_topic: format!("{MQTT_DISCOVERY_TOPIC}/sensor/{unique_id}/temperature/config"),
+            unique_id: format!("{unique_id}_temperature"),
+            specific: DeviceSpecificConfig::Sensor {
+                state_topic: state_topic_for_dev_id(client_id, &sensor.identifier),
+                device_class: Some(DeviceClass::Temperature),
+                state_class: Some(StateClass::Measurement),
+                unit_of_measurement: Some("°C".to_owned()),
+                value_template: Some("{{ value_json.temperature }}".to_owned()),
+            },
+            availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
+            device: DevicePayload {
+                name: sensor.name.clone(),
+                identifiers: vec![unique_id, sensor.id.clone()],
+                manufacturer: Some("Maxim".to_owned()),
+                model: Some(sensor.model.to_owned()),
                 sw_version: None,
             },
-            name: conf.name,
+            name: format!("{} temperature", sensor.name),
         }
     }
 
@@ -338,6 +735,16 @@ impl ConfigPayload {
                     value_template: Some("{{ value_json.battery_active_discharge_power }}".to_owned()),
                 },
             ),
+            (
+                "battery_net_power",
+                DeviceSpecificConfig::Sensor {
+                    state_topic: state_topic.clone(),
+                    device_class: Some(DeviceClass::Power),
+                    state_class: Some(StateClass::Measurement),
+                    unit_of_measurement: Some("W".to_owned()),
+                    value_template: Some("{{ value_json.battery_net_power }}".to_owned()),
+                },
+            ),
             (
                 "state_of_charge",
                 DeviceSpecificConfig::Sensor {
@@ -378,8 +785,29 @@ impl ConfigPayload {
                     value_template: Some("{{ value_json.grid_backfeed_power }}".to_owned()),
                 },
             ),
+            (
+                "grid_net_power",
+                DeviceSpecificConfig::Sensor {
+                    state_topic: state_topic.clone(),
+                    device_class: Some(DeviceClass::Power),
+                    state_class: Some(StateClass::Measurement),
+                    unit_of_measurement: Some("W".to_owned()),
+                    value_template: Some("{{ value_json.grid_net_power }}".to_owned()),
+                },
+            ),
         ];
 
+        let sensors = sensors.into_iter().map(|(sensor_name, mut sensor)| {
+            if let (config::StateTopics::PerField, DeviceSpecificConfig::Sensor { state_topic, value_template, .. }) =
+                (conf.state_topics, &mut sensor)
+            {
+                *state_topic = state_field_topic(state_topic, sensor_name);
+                *value_template = None;
+            }
+
+            (sensor_name, sensor)
+        });
+
         let unique_id = format!("{client_id}_{dev_id}", dev_id = dev_id.0);
 
         let mut identifiers = vec![unique_id.clone()];
@@ -394,9 +822,11 @@ impl ConfigPayload {
             .or_else(|| specs.map(|specs| Self::format_sunspec_software_version(specs.software_version_ens)));
 
         sensors
-            .into_iter()
             .map(move |(sensor_name, sensor)| ConfigPayload {
-                config_topic: format!("{MQTT_DISCOVERY_TOPIC}/sensor/{unique_id}/{sensor_name}/config"),
+                config_topic: format!(
+                    "{MQTT_DISCOVERY_TOPIC}/{component}/{unique_id}/{sensor_name}/config",
+                    component = sensor.component()
+                ),
                 unique_id: format!("{unique_id}_{sensor_name}"),
                 availability: vec![AvailabilityPayload { topic: mqtt_avail_topic(client_id) }],
                 device: DevicePayload {
@@ -0,0 +1,311 @@
+use crate::config::{Identifier, OneWireConfig};
+use serde::Serialize;
+use std::{fs, io, path::PathBuf};
+use thiserror::Error;
+
+/// Family codes of the supported temperature sensors and their models.
+const TEMPERATURE_FAMILIES: [(&str, &str); 5] = [
+    ("10", "DS18S20"),
+    ("22", "DS1822"),
+    ("28", "DS18B20"),
+    ("3b", "DS1825"),
+    ("42", "DS28EA00"),
+];
+
+/// The scratchpad holds 85 °C after powering up, until the first conversion finished. As 85 °C is a valid reading,
+/// it is only taken for the reset value if the reserved bytes 6 and 7 of the scratchpad hold their reset values too.
+const POWER_ON_RESET_MILLIDEGREES: i32 = 85_000;
+const POWER_ON_RESET_RESERVED: [u8; 2] = [0x0c, 0x10];
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("unexpected sensor output {0:?}")]
+    Format(String),
+    #[error("CRC mismatch")]
+    Crc,
+    #[error("the sensor did not respond")]
+    NoResponse,
+    #[error("the sensor reported its power-on reset value")]
+    PowerOnReset,
+}
+
+/// `stale` is only set for states restored at startup that were not yet refreshed by the sensor.
+#[derive(Serialize, Debug)]
+pub struct TemperatureState {
+    pub temperature: f64,
+    pub stale: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sensor {
+    /// id of the sensor on the bus, e.g. `28-0316a2795bff`
+    pub id: String,
+    pub name: String,
+    pub model: &'static str,
+    pub identifier: Identifier,
+    path: PathBuf,
+}
+
+impl Sensor {
+    /// Reads the temperature in °C, this blocks for the duration of a conversion (up to 750 ms).
+    /// `w1_slave` is preferred as it allows checking the CRC and the power-on reset value, `temperature` is only
+    /// provided by newer kernels.
+    pub fn read_temperature(&self) -> Result<f64, Error> {
+        let millidegrees = match fs::read_to_string(self.path.join("w1_slave")) {
+            Ok(content) => parse_w1_slave(&content)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                parse_temperature(&fs::read_to_string(self.path.join("temperature"))?)?
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(f64::from(millidegrees) / 1000.0)
+    }
+}
+
+/// Lists the temperature sensors in the devices directory of the w1 bus, ordered by their id.
+pub fn discover(conf: &OneWireConfig) -> io::Result<Vec<Sensor>> {
+    let mut sensors = Vec::new();
+
+    for entry in fs::read_dir(&conf.sysfs_root)? {
+        let entry = entry?;
+        let id = entry.file_name().to_string_lossy().into_owned();
+
+        // the directory also holds the bus masters, e.g. `w1_bus_master1`
+        let Some((family, serial)) = id.split_once('-') else {
+            continue;
+        };
+
+        let Some(&(_, model)) = TEMPERATURE_FAMILIES
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(family))
+        else {
+            continue;
+        };
+
+        sensors.push(Sensor {
+            name: conf.names.get(&id).cloned().unwrap_or_else(|| format!("1-Wire {id}")),
+            model,
+            identifier: Identifier(format!("w1_{family}_{serial}").to_lowercase()),
+            path: entry.path(),
+            id,
+        });
+    }
+
+    sensors.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(sensors)
+}
+
+/// `w1_slave` holds the scratchpad and the CRC check of the kernel, followed by the scratchpad and the temperature:
+/// ```text
+/// 72 01 4b 46 7f ff 0e 10 57 : crc=57 YES
+/// 72 01 4b 46 7f ff 0e 10 57 t=23125
+/// ```
+fn parse_w1_slave(content: &str) -> Result<i32, Error> {
+    let format_error = || Error::Format(content.trim().to_owned());
+
+    let mut lines = content.lines();
+    let (Some(crc_line), Some(temperature_line)) = (lines.next(), lines.next()) else {
+        return Err(format_error());
+    };
+
+    let (scratchpad, crc_check) = crc_line.split_once(':').ok_or_else(format_error)?;
+    let scratchpad = scratchpad
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format_error())?;
+
+    let [data @ .., crc] = scratchpad.as_slice() else {
+        return Err(format_error());
+    };
+
+    if data.len() != 8 {
+        return Err(format_error());
+    }
+
+    // a missing sensor reads as all zeros, which has a valid CRC
+    if scratchpad.iter().all(|&byte| byte == 0) {
+        return Err(Error::NoResponse);
+    }
+
+    if !crc_check.trim_end().ends_with("YES") || crc8(data) != *crc {
+        return Err(Error::Crc);
+    }
+
+    let (_, millidegrees) = temperature_line.rsplit_once("t=").ok_or_else(format_error)?;
+    let millidegrees = millidegrees.trim().parse().map_err(|_| format_error())?;
+
+    if millidegrees == POWER_ON_RESET_MILLIDEGREES && data[6..] == POWER_ON_RESET_RESERVED {
+        return Err(Error::PowerOnReset);
+    }
+
+    Ok(millidegrees)
+}
+
+/// `temperature` holds the temperature in millidegrees, the kernel already checked the CRC.
+fn parse_temperature(content: &str) -> Result<i32, Error> {
+    content
+        .trim()
+        .parse()
+        .map_err(|_| Error::Format(content.trim().to_owned()))
+}
+
+/// The Dallas/Maxim CRC-8 of the scratchpad (polynomial x^8 + x^5 + x^4 + 1).
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(
+            crc ^ byte,
+            |crc, _| {
+                if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x8c
+                } else {
+                    crc >> 1
+                }
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn discover_lists_temperature_sensors_ordered_by_id() {
+        let root = tempfile::tempdir().unwrap();
+
+        for id in [
+            "w1_bus_master1",
+            "28-0316a2795bff",
+            "3a-00000011c4e2",
+            "10-000801b5a7c1",
+        ] {
+            fs::create_dir(root.path().join(id)).unwrap();
+        }
+
+        let conf = OneWireConfig {
+            sysfs_root: root.path().to_owned(),
+            polling_interval_ms: 30000,
+            names: HashMap::from([("28-0316a2795bff".to_owned(), "Boiler".to_owned())]),
+        };
+
+        let sensors = discover(&conf).unwrap();
+
+        let summary: Vec<_> = sensors
+            .iter()
+            .map(|sensor| {
+                (
+                    sensor.id.as_str(),
+                    sensor.name.as_str(),
+                    sensor.model,
+                    sensor.identifier.0.as_str(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (
+                    "10-000801b5a7c1",
+                    "1-Wire 10-000801b5a7c1",
+                    "DS18S20",
+                    "w1_10_000801b5a7c1"
+                ),
+                ("28-0316a2795bff", "Boiler", "DS18B20", "w1_28_0316a2795bff"),
+            ]
+        );
+        assert_eq!(sensors[1].path, root.path().join("28-0316a2795bff"));
+    }
+
+    #[test]
+    fn discover_fails_without_bus() {
+        let root = tempfile::tempdir().unwrap();
+
+        let conf = OneWireConfig {
+            sysfs_root: root.path().join("missing"),
+            polling_interval_ms: 30000,
+            names: HashMap::new(),
+        };
+
+        assert!(discover(&conf).is_err());
+    }
+
+    #[test]
+    fn parse_w1_slave_valid() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(content).unwrap(), 23125);
+    }
+
+    #[test]
+    fn parse_w1_slave_negative() {
+        let content = "5e ff 4b 46 7f ff 02 10 b6 : crc=b6 YES\n5e ff 4b 46 7f ff 02 10 b6 t=-10125\n";
+        assert_eq!(parse_w1_slave(content).unwrap(), -10125);
+    }
+
+    #[test]
+    fn parse_w1_slave_crc_rejected_by_kernel() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert!(matches!(parse_w1_slave(content), Err(Error::Crc)));
+    }
+
+    #[test]
+    fn parse_w1_slave_crc_mismatch() {
+        let content = "72 01 4b 46 7f ff 0e 10 58 : crc=58 YES\n72 01 4b 46 7f ff 0e 10 58 t=23125\n";
+        assert!(matches!(parse_w1_slave(content), Err(Error::Crc)));
+    }
+
+    #[test]
+    fn parse_w1_slave_all_zeros() {
+        let content = "00 00 00 00 00 00 00 00 00 : crc=00 YES\n00 00 00 00 00 00 00 00 00 t=0\n";
+        assert!(matches!(parse_w1_slave(content), Err(Error::NoResponse)));
+    }
+
+    #[test]
+    fn parse_w1_slave_missing_temperature() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57\n";
+        assert!(matches!(parse_w1_slave(content), Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn parse_w1_slave_truncated() {
+        assert!(matches!(
+            parse_w1_slave("72 01 4b : crc=57 YES\n"),
+            Err(Error::Format(_))
+        ));
+        assert!(matches!(parse_w1_slave(""), Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn parse_w1_slave_power_on_reset() {
+        let content = "50 05 4b 46 7f ff 0c 10 1c : crc=1c YES\n50 05 4b 46 7f ff 0c 10 1c t=85000\n";
+        assert!(matches!(parse_w1_slave(content), Err(Error::PowerOnReset)));
+    }
+
+    #[test]
+    fn parse_w1_slave_measured_85_degrees() {
+        let content = "50 05 4b 46 7f ff 0b 10 72 : crc=72 YES\n50 05 4b 46 7f ff 0b 10 72 t=85000\n";
+        assert_eq!(parse_w1_slave(content).unwrap(), 85000);
+    }
+
+    #[test]
+    fn parse_temperature_millidegrees() {
+        assert_eq!(parse_temperature("23125\n").unwrap(), 23125);
+        assert_eq!(parse_temperature("-10125\n").unwrap(), -10125);
+        assert!(matches!(parse_temperature("\n"), Err(Error::Format(_))));
+        assert!(matches!(parse_temperature("23.125\n"), Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn crc8_datasheet_example() {
+        // ROM code example of Maxim application note 27, the CRC is the last byte of the ROM code
+        assert_eq!(crc8(&[0x02, 0x1c, 0xb8, 0x01, 0x00, 0x00, 0x00]), 0xa2);
+        assert_eq!(crc8(&[0x72, 0x01, 0x4b, 0x46, 0x7f, 0xff, 0x0e, 0x10]), 0x57);
+        assert_eq!(crc8(&[]), 0x00);
+    }
+}
@@ -0,0 +1,355 @@
+use crate::{config::OutboxConfig, metrics::metrics, mqtt, state_store::write_atomically};
+use paho_mqtt::{AsyncClient, Message};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::{self, File},
+    future::Future,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::{info, warn};
+
+/// Upper bound of the messages published by one flush, so that a large backlog doesn't hold up the main loop.
+const FLUSH_BATCH_SIZE: usize = 32;
+
+/// Time the broker has to acknowledge a publish, a broker that stops acknowledging must not block the main loop.
+/// The message is kept and published again on the next retry, which may deliver it twice.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingMessage {
+    topic: String,
+    payload: String,
+    qos: i32,
+    retained: bool,
+    queued_at: SystemTime,
+}
+
+impl PendingMessage {
+    fn new(msg: &Message, queued_at: SystemTime) -> Self {
+        Self {
+            topic: msg.topic().to_owned(),
+            payload: msg.payload_str().into_owned(),
+            qos: msg.qos(),
+            retained: msg.retained(),
+            queued_at,
+        }
+    }
+
+    fn to_message(&self) -> Message {
+        if self.retained {
+            Message::new_retained(self.topic.as_str(), self.payload.as_bytes(), self.qos)
+        } else {
+            Message::new(self.topic.as_str(), self.payload.as_bytes(), self.qos)
+        }
+    }
+}
+
+/// Outbound messages that could not be published yet, e.g. because the broker is restarting.
+/// Messages are published strictly in order, the oldest ones are dropped once `max_messages` is exceeded
+/// or they are older than `max_age`. If a path is configured, pending messages survive a restart of the bridge.
+/// They are only written when `persist` is called, i.e. periodically and on shutdown, not on every change.
+pub struct Outbox {
+    queue: VecDeque<PendingMessage>,
+    max_messages: usize,
+    max_age: Duration,
+    path: Option<PathBuf>,
+    persisted: bool,
+    /// whether the queue changed since it was last persisted
+    dirty: bool,
+    /// whether the last flush failed to publish, further attempts wait for the next retry
+    stalled: bool,
+}
+
+impl Outbox {
+    pub fn new(conf: OutboxConfig) -> Self {
+        let queue = match conf.path.as_deref().map(load) {
+            Some(Ok(queue)) => {
+                if !queue.is_empty() {
+                    info!(pending = queue.len(), "Restored pending messages");
+                }
+
+                queue
+            },
+            Some(Err(e)) => {
+                warn!("Unable to restore pending messages: {e}");
+                VecDeque::new()
+            },
+            None => VecDeque::new(),
+        };
+
+        Self {
+            persisted: !queue.is_empty(),
+            queue,
+            max_messages: conf.max_messages,
+            max_age: Duration::from_millis(conf.max_age_ms),
+            path: conf.path,
+            dirty: false,
+            stalled: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Whether messages are left after a successful flush, which can be published right away.
+    pub fn has_backlog(&self) -> bool {
+        !self.stalled && !self.queue.is_empty()
+    }
+
+    pub async fn publish(&mut self, client: &AsyncClient, messages: impl IntoIterator<Item = Message>) {
+        self.enqueue(messages);
+
+        // while the broker is unreachable, new messages don't fail one by one but wait for the next retry
+        if !self.stalled {
+            self.flush(client).await;
+        }
+    }
+
+    fn enqueue(&mut self, messages: impl IntoIterator<Item = Message>) {
+        let now = SystemTime::now();
+        let before = self.queue.len();
+        self.queue
+            .extend(messages.into_iter().map(|msg| PendingMessage::new(&msg, now)));
+        self.dirty |= self.queue.len() != before;
+
+        let overflow = self.queue.len().saturating_sub(self.max_messages);
+        if overflow > 0 {
+            self.queue.drain(..overflow);
+            warn!(dropped = overflow, "Outbox full, dropped oldest messages");
+        }
+    }
+
+    /// Publishes up to `FLUSH_BATCH_SIZE` pending messages, the rest is left for the next flush.
+    pub async fn flush(&mut self, client: &AsyncClient) {
+        self.flush_with(|msg| mqtt::publish(client, msg)).await;
+    }
+
+    async fn flush_with<F, Fut, E>(&mut self, mut publish: F)
+    where
+        F: FnMut(Message) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        self.drop_expired();
+        self.stalled = false;
+
+        for _ in 0..FLUSH_BATCH_SIZE {
+            let Some(pending) = self.queue.front() else {
+                break;
+            };
+
+            match tokio::time::timeout(PUBLISH_TIMEOUT, publish(pending.to_message())).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => {
+                    warn!(pending = self.queue.len(), "Unable to publish, retrying later: {e}");
+                    self.stalled = true;
+                    break;
+                },
+                Err(_) => {
+                    warn!(
+                        pending = self.queue.len(),
+                        "Publish not acknowledged in time, retrying later"
+                    );
+                    self.stalled = true;
+                    break;
+                },
+            }
+
+            self.queue.pop_front();
+            self.dirty = true;
+        }
+
+        metrics()
+            .queue_depth
+            .with_label_values(&["outbox"])
+            .set(self.queue.len() as i64);
+    }
+
+    fn drop_expired(&mut self) {
+        let now = SystemTime::now();
+        let before = self.queue.len();
+
+        self.queue.retain(|pending| {
+            now.duration_since(pending.queued_at)
+                .is_ok_and(|age| age <= self.max_age)
+        });
+
+        let expired = before - self.queue.len();
+        if expired > 0 {
+            self.dirty = true;
+            warn!(dropped = expired, "Dropped expired messages from outbox");
+        }
+    }
+
+    /// Writes the pending messages to disk if they changed since they were last written.
+    pub fn persist(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        if !self.dirty {
+            return;
+        }
+
+        // nothing is written to disk as long as every message can be published right away
+        let result = if !self.queue.is_empty() {
+            store(path, &self.queue)
+        } else if self.persisted {
+            fs::remove_file(path).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+        } else {
+            self.dirty = false;
+            return;
+        };
+
+        match result {
+            Ok(()) => {
+                self.persisted = !self.queue.is_empty();
+                self.dirty = false;
+            },
+            Err(e) => warn!(path = %path.display(), "Unable to persist pending messages: {e}"),
+        }
+    }
+}
+
+fn load(path: &Path) -> io::Result<VecDeque<PendingMessage>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(VecDeque::new()),
+        Err(e) => return Err(e),
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+fn store(path: &Path, queue: &VecDeque<PendingMessage>) -> io::Result<()> {
+    write_atomically(path, |writer| {
+        for pending in queue {
+            serde_json::to_writer(&mut *writer, pending)?;
+            writer.write_all(b"\n")?;
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    fn outbox(max_messages: usize, path: Option<PathBuf>) -> Outbox {
+        Outbox::new(OutboxConfig { max_messages, max_age_ms: 60 * 1000, path })
+    }
+
+    fn messages(topics: std::ops::Range<usize>) -> impl Iterator<Item = Message> {
+        topics.map(|topic| Message::new(format!("topic/{topic}"), "payload", 1))
+    }
+
+    fn topics(outbox: &Outbox) -> Vec<&str> {
+        outbox.queue.iter().map(|pending| pending.topic.as_str()).collect()
+    }
+
+    /// Flushes once, returns the topics that were published.
+    async fn flush(outbox: &mut Outbox) -> Vec<String> {
+        let mut published = Vec::new();
+        outbox
+            .flush_with(|msg| {
+                published.push(msg.topic().to_owned());
+                async { Ok::<_, Infallible>(()) }
+            })
+            .await;
+        published
+    }
+
+    #[tokio::test]
+    async fn flush_publishes_in_batches() {
+        let mut outbox = outbox(100, None);
+        outbox.enqueue(messages(0..40));
+
+        let published = flush(&mut outbox).await;
+        assert_eq!(published.len(), FLUSH_BATCH_SIZE);
+        assert_eq!(published.first().map(String::as_str), Some("topic/0"));
+        assert!(outbox.has_backlog());
+
+        let published = flush(&mut outbox).await;
+        assert_eq!(published.len(), 40 - FLUSH_BATCH_SIZE);
+        assert_eq!(published.last().map(String::as_str), Some("topic/39"));
+        assert!(outbox.is_empty());
+        assert!(!outbox.has_backlog());
+    }
+
+    #[tokio::test]
+    async fn failed_publish_stalls_the_outbox() {
+        let mut outbox = outbox(100, None);
+        outbox.enqueue(messages(0..3));
+
+        let mut attempts = 0;
+        outbox
+            .flush_with(|_| {
+                attempts += 1;
+                async { Err("connection lost") }
+            })
+            .await;
+
+        assert_eq!(attempts, 1);
+        assert_eq!(topics(&outbox), ["topic/0", "topic/1", "topic/2"]);
+        assert!(!outbox.has_backlog());
+
+        assert_eq!(flush(&mut outbox).await, ["topic/0", "topic/1", "topic/2"]);
+    }
+
+    #[test]
+    fn backlog_drops_the_oldest_messages() {
+        let mut outbox = outbox(3, None);
+
+        outbox.enqueue(messages(0..2));
+        outbox.enqueue(messages(2..5));
+
+        assert_eq!(topics(&outbox), ["topic/2", "topic/3", "topic/4"]);
+    }
+
+    #[tokio::test]
+    async fn expired_messages_are_dropped() {
+        let mut outbox = Outbox::new(OutboxConfig { max_messages: 100, max_age_ms: 0, path: None });
+        outbox.enqueue(messages(0..2));
+        std::thread::sleep(Duration::from_millis(5));
+
+        assert!(flush(&mut outbox).await.is_empty());
+        assert!(outbox.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pending_messages_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.jsonl");
+
+        let mut outbox = outbox(100, Some(path.clone()));
+        outbox.enqueue([Message::new_retained("topic/retained", "on", 1)]);
+        outbox.enqueue(messages(0..1));
+        outbox.persist();
+
+        let mut restored = self::outbox(100, Some(path.clone()));
+        assert_eq!(topics(&restored), ["topic/retained", "topic/0"]);
+        assert!(restored.queue[0].retained);
+        assert_eq!(restored.queue[0].payload, "on");
+
+        flush(&mut restored).await;
+        restored.persist();
+        assert!(!path.exists());
+    }
+}
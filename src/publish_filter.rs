@@ -0,0 +1,162 @@
+use crate::config::{Deadband, Deadbands, PublishConfig};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio::time::{Duration, Instant};
+
+#[derive(Error, Debug)]
+#[error("deadband of unknown state field {field}, deadbands can be set for {}", known.join(", "))]
+pub struct UnknownFieldError {
+    field: String,
+    known: Vec<String>,
+}
+
+/// Decides whether a state needs to be published by comparing it field by field
+/// against the last published state.
+#[derive(Clone)]
+pub struct PublishFilter {
+    min_interval: Duration,
+    heartbeat_interval: Option<Duration>,
+    deadbands: Deadbands,
+    last_published: Option<(Instant, Map<String, Value>)>,
+}
+
+impl PublishFilter {
+    /// Deadbands are only accepted for the numeric fields of `sample`, a state of the kind that is filtered.
+    pub fn new(conf: PublishConfig, sample: &impl Serialize) -> Result<Self, UnknownFieldError> {
+        let known: Vec<_> = match serde_json::to_value(sample) {
+            Ok(Value::Object(fields)) => fields
+                .into_iter()
+                .filter(|(_, value)| value.is_number())
+                .map(|(field, _)| field)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        if let Some(field) = conf.deadbands.0.keys().find(|field| !known.contains(field)) {
+            return Err(UnknownFieldError { field: field.clone(), known });
+        }
+
+        Ok(Self {
+            min_interval: Duration::from_millis(conf.min_interval_ms),
+            heartbeat_interval: conf.heartbeat_interval_ms.map(Duration::from_millis),
+            deadbands: conf.deadbands,
+            last_published: None,
+        })
+    }
+
+    fn field_changed(&self, field: &str, old: Option<&Value>, new: &Value) -> bool {
+        let (Some(old), Some(new)) = (old.and_then(Value::as_f64), new.as_f64()) else {
+            return old != Some(new);
+        };
+
+        let diff = (new - old).abs();
+        let band = match self.deadbands.get(field) {
+            Some(Deadband::Absolute(band)) => band,
+            Some(Deadband::Percentage(percentage)) => old.abs() * percentage / 100.0,
+            None => 0.0,
+        };
+
+        diff > 0.0 && diff >= band
+    }
+
+    /// Returns `true` and remembers the state if it should be published now.
+    pub fn should_publish(&mut self, state: &impl Serialize) -> bool {
+        self.should_publish_at(state, Instant::now())
+    }
+
+    fn should_publish_at(&mut self, state: &impl Serialize, now: Instant) -> bool {
+        let Ok(Value::Object(fields)) = serde_json::to_value(state) else {
+            return true;
+        };
+
+        let publish = match &self.last_published {
+            None => true,
+            Some((last_publish, _)) if now - *last_publish < self.min_interval => false,
+            Some((last_publish, _)) if self.heartbeat_interval.is_some_and(|hb| now - *last_publish >= hb) => true,
+            Some((_, last_fields)) => fields
+                .iter()
+                .any(|(field, value)| self.field_changed(field, last_fields.get(field), value)),
+        };
+
+        if publish {
+            self.last_published = Some((now, fields));
+        }
+
+        publish
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(yaml: &str) -> PublishFilter {
+        let sample = json!({ "state": "ready", "power": 0, "state_of_charge": 0 });
+        PublishFilter::new(serde_yaml::from_str(yaml).unwrap(), &sample).unwrap()
+    }
+
+    fn state(power: i64, state_of_charge: u64) -> Value {
+        json!({ "state": "ready", "power": power, "state_of_charge": state_of_charge })
+    }
+
+    #[test]
+    fn deadbands_of_unknown_or_non_numeric_fields_are_rejected() {
+        let sample = state(0, 0);
+
+        for deadbands in ["{ pwoer: 5 }", "{ state: 1 }"] {
+            let conf = serde_yaml::from_str(&format!("deadbands: {deadbands}")).unwrap();
+            assert!(PublishFilter::new(conf, &sample).is_err());
+        }
+    }
+
+    #[test]
+    fn changes_within_the_deadband_are_not_published() {
+        let mut filter = filter("deadbands: { power: 10, state_of_charge: 5% }");
+        let now = Instant::now();
+
+        assert!(filter.should_publish_at(&state(100, 50), now));
+        assert!(!filter.should_publish_at(&state(109, 50), now));
+        assert!(!filter.should_publish_at(&state(91, 52), now));
+        assert!(filter.should_publish_at(&state(110, 50), now));
+        // compared against the last published state, not the last seen one
+        assert!(!filter.should_publish_at(&state(110, 52), now));
+        assert!(filter.should_publish_at(&state(110, 53), now));
+    }
+
+    #[test]
+    fn fields_without_deadband_publish_every_change() {
+        let mut filter = filter("deadbands: { power: 10 }");
+        let now = Instant::now();
+
+        assert!(filter.should_publish_at(&state(100, 50), now));
+        assert!(!filter.should_publish_at(&state(100, 50), now));
+        assert!(filter.should_publish_at(&state(100, 51), now));
+        assert!(filter.should_publish_at(
+            &json!({ "state": "charging", "power": 100, "state_of_charge": 51 }),
+            now
+        ));
+    }
+
+    #[test]
+    fn changes_are_not_published_within_the_min_interval() {
+        let mut filter = filter("min_interval_ms: 5000");
+        let now = Instant::now();
+
+        assert!(filter.should_publish_at(&state(100, 50), now));
+        assert!(!filter.should_publish_at(&state(200, 50), now + Duration::from_secs(4)));
+        assert!(filter.should_publish_at(&state(200, 50), now + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn unchanged_states_are_republished_after_the_heartbeat_interval() {
+        let mut filter = filter("heartbeat_interval_ms: 60000");
+        let now = Instant::now();
+
+        assert!(filter.should_publish_at(&state(100, 50), now));
+        assert!(!filter.should_publish_at(&state(100, 50), now + Duration::from_secs(59)));
+        assert!(filter.should_publish_at(&state(100, 50), now + Duration::from_secs(60)));
+        assert!(!filter.should_publish_at(&state(100, 50), now + Duration::from_secs(61)));
+    }
+}
@@ -0,0 +1,487 @@
+use crate::{
+    config::{ActionConfig, ConditionTest, InputEdge, OutputLineConfig, RuleConfig, RuleTriggerConfig},
+    covers::CoverCommand,
+    http::{DeviceInfo, DeviceKind},
+};
+use serde_json::Value;
+use std::{collections::HashMap, path::PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("rule {rule} refers to unknown device {device}")]
+    UnknownDevice { rule: String, device: String },
+    #[error("rule {rule} refers to unknown cover {cover}")]
+    UnknownCover { rule: String, cover: String },
+    #[error("rule {0} is triggered by a schedule, its conditions need a device")]
+    MissingDevice(String),
+    #[error("rule {rule} is triggered by {topic}, a topic of the bridge itself, use a state trigger instead")]
+    BridgeTopic { rule: String, topic: String },
+}
+
+#[derive(Debug)]
+enum RuleTrigger {
+    Mqtt(String),
+    State(String),
+    Input(InputEdge),
+    Schedule,
+}
+
+#[derive(Debug)]
+struct Condition {
+    /// key of the latest state to test instead of the trigger value
+    device: Option<String>,
+    field: Option<String>,
+    test: ConditionTest,
+}
+
+impl Condition {
+    fn holds(&self, trigger_value: Option<&Value>, states: &HashMap<String, Value>) -> bool {
+        let value = match &self.device {
+            Some(device) => states.get(device),
+            None => trigger_value,
+        };
+
+        let value = match (value, &self.field) {
+            (Some(value), Some(field)) => field.split('.').try_fold(value, |value, key| value.get(key)),
+            (value, None) => value,
+            (None, Some(_)) => None,
+        };
+
+        let Some(value) = value else {
+            return false;
+        };
+
+        match &self.test {
+            ConditionTest::Equals(expected) => equals(value, expected),
+            ConditionTest::NotEquals(expected) => !equals(value, expected),
+            ConditionTest::Below(limit) => number(value).is_some_and(|value| value < *limit),
+            ConditionTest::Above(limit) => number(value).is_some_and(|value| value > *limit),
+        }
+    }
+}
+
+/// Numbers may also be given as strings, as MQTT payloads usually are.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Strings are compared ignoring case, so `Discharging` matches the published `discharging`.
+fn equals(value: &Value, expected: &Value) -> bool {
+    match (value, expected) {
+        (Value::String(value), Value::String(expected)) if value.eq_ignore_ascii_case(expected) => true,
+        _ => match (number(value), number(expected)) {
+            (Some(value), Some(expected)) => value == expected,
+            _ => value == expected,
+        },
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+    Cover {
+        topic: String,
+        command: CoverCommand,
+    },
+    /// index into `Rules::outputs`
+    Gpio {
+        output: usize,
+        active: bool,
+    },
+    Publish {
+        topic: String,
+        payload: String,
+        retain: bool,
+    },
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    trigger: RuleTrigger,
+    conditions: Vec<Condition>,
+    actions: Vec<Action>,
+    /// whether the conditions held when the rule was last triggered
+    holding: bool,
+}
+
+pub enum Event<'a> {
+    Mqtt { topic: &'a str, payload: &'a str },
+    State { topic: &'a str, state: &'a Value },
+    Input { rule: usize, active: bool },
+    Schedule(usize),
+}
+
+/// The configured rules, in the order of the config.
+#[derive(Debug)]
+pub struct Rules {
+    rules: Vec<Rule>,
+    /// output lines switched by the actions, each line only once
+    pub outputs: Vec<(Option<PathBuf>, OutputLineConfig)>,
+}
+
+impl Rules {
+    /// Device identifiers are resolved to the keys of their latest states, which are their topics.
+    /// MQTT triggers on the topics of the bridge are rejected, they would shadow its commands.
+    pub fn from_config(confs: &[RuleConfig], devices: &[DeviceInfo], client_id: &str) -> Result<Self, Error> {
+        let mut outputs: Vec<(Option<PathBuf>, OutputLineConfig)> = Vec::new();
+        let mut rules = Vec::new();
+
+        for conf in confs {
+            let device_topic = |identifier: &str| {
+                devices
+                    .iter()
+                    .find(|device| device.identifier == identifier)
+                    .map(|device| device.topic.clone())
+                    .ok_or_else(|| Error::UnknownDevice { rule: conf.name.clone(), device: identifier.to_owned() })
+            };
+
+            let trigger = match &conf.trigger {
+                RuleTriggerConfig::Mqtt { mqtt } => {
+                    if mqtt.strip_prefix(client_id).is_some_and(|rest| rest.starts_with('/')) {
+                        return Err(Error::BridgeTopic { rule: conf.name.clone(), topic: mqtt.clone() });
+                    }
+
+                    RuleTrigger::Mqtt(mqtt.clone())
+                },
+                RuleTriggerConfig::State { state } => RuleTrigger::State(device_topic(&state.0)?),
+                RuleTriggerConfig::Input { on, .. } => RuleTrigger::Input(*on),
+                RuleTriggerConfig::Schedule(_) => RuleTrigger::Schedule,
+            };
+
+            let conditions = conf
+                .conditions
+                .iter()
+                .map(|condition| {
+                    let device = match &condition.device {
+                        Some(device) => Some(device_topic(&device.0)?),
+                        None if matches!(trigger, RuleTrigger::Schedule) => {
+                            return Err(Error::MissingDevice(conf.name.clone()));
+                        },
+                        None => None,
+                    };
+
+                    Ok(Condition {
+                        device,
+                        field: condition.field.clone(),
+                        test: condition.test.clone(),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+
+            let actions = conf
+                .actions
+                .iter()
+                .map(|action| match action {
+                    ActionConfig::Cover { cover, command } => {
+                        let topic = devices
+                            .iter()
+                            .find(|device| {
+                                device.identifier == cover.0
+                                    && matches!(device.kind, DeviceKind::Cover | DeviceKind::CoverGroup)
+                            })
+                            .map(|device| device.topic.clone())
+                            .ok_or_else(|| Error::UnknownCover { rule: conf.name.clone(), cover: cover.0.clone() })?;
+
+                        Ok(Action::Cover { topic, command: (*command).into() })
+                    },
+                    ActionConfig::Gpio { gpio, chip, active } => {
+                        let output = match outputs
+                            .iter()
+                            .position(|(output_chip, output)| *output_chip == *chip && output.line == gpio.line)
+                        {
+                            Some(output) => output,
+                            None => {
+                                outputs.push((chip.clone(), gpio.clone()));
+                                outputs.len() - 1
+                            },
+                        };
+
+                        Ok(Action::Gpio { output, active: *active })
+                    },
+                    ActionConfig::Publish { publish, payload, retain } => Ok(Action::Publish {
+                        topic: publish.clone(),
+                        payload: payload.clone(),
+                        retain: *retain,
+                    }),
+                })
+                .collect::<Result<_, _>>()?;
+
+            rules.push(Rule {
+                name: conf.name.clone(),
+                trigger,
+                conditions,
+                actions,
+                holding: false,
+            });
+        }
+
+        Ok(Self { rules, outputs })
+    }
+
+    /// Topics of the MQTT triggers.
+    pub fn mqtt_topics(&self) -> impl Iterator<Item = &String> {
+        self.rules.iter().filter_map(|rule| match &rule.trigger {
+            RuleTrigger::Mqtt(topic) => Some(topic),
+            _ => None,
+        })
+    }
+
+    /// Returns the names and actions of the rules to run. Rules with conditions that are triggered by MQTT, states or
+    /// inputs only run when their conditions start to hold, so repeated values don't repeat the actions. All other
+    /// rules run every time they are triggered.
+    pub fn evaluate(&mut self, event: &Event, states: &HashMap<String, Value>) -> Vec<(String, Vec<Action>)> {
+        let mut triggered = Vec::new();
+
+        for (index, rule) in self.rules.iter_mut().enumerate() {
+            let trigger_value = match (&rule.trigger, event) {
+                (RuleTrigger::Mqtt(topic), Event::Mqtt { topic: event_topic, payload }) if topic == event_topic => {
+                    Some(serde_json::from_str(payload).unwrap_or_else(|_| Value::String((*payload).to_owned())))
+                },
+                (RuleTrigger::State(topic), Event::State { topic: event_topic, state }) if topic == event_topic => {
+                    Some((*state).clone())
+                },
+                (RuleTrigger::Input(on), &Event::Input { rule, active }) if rule == index => {
+                    let edge = match on {
+                        InputEdge::Rising => active,
+                        InputEdge::Falling => !active,
+                        InputEdge::Both => true,
+                    };
+
+                    if !edge {
+                        continue;
+                    }
+
+                    Some(Value::from(if active { "ON" } else { "OFF" }))
+                },
+                (RuleTrigger::Schedule, &Event::Schedule(rule)) if rule == index => None,
+                _ => continue,
+            };
+
+            let holds = rule
+                .conditions
+                .iter()
+                .all(|condition| condition.holds(trigger_value.as_ref(), states));
+
+            let edge_triggered = !matches!(rule.trigger, RuleTrigger::Schedule) && !rule.conditions.is_empty();
+            let run = holds && !(edge_triggered && rule.holding);
+
+            rule.holding = holds;
+
+            if run {
+                triggered.push((rule.name.clone(), rule.actions.clone()));
+            }
+        }
+
+        triggered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const BATTERY: &str = "bridge/varta_element_1/state";
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            DeviceInfo {
+                identifier: "varta_element_1".to_owned(),
+                name: "Varta Element".to_owned(),
+                kind: DeviceKind::Sunspec,
+                topic: BATTERY.to_owned(),
+            },
+            DeviceInfo {
+                identifier: "velux_integra_1".to_owned(),
+                name: "Velux 1".to_owned(),
+                kind: DeviceKind::Cover,
+                topic: "bridge/velux_integra_1/set".to_owned(),
+            },
+        ]
+    }
+
+    fn rules(yaml: &str) -> Rules {
+        let confs: Vec<RuleConfig> = serde_yaml::from_str(yaml).unwrap();
+        Rules::from_config(&confs, &devices(), "bridge").unwrap()
+    }
+
+    fn names(triggered: Vec<(String, Vec<Action>)>) -> Vec<String> {
+        triggered.into_iter().map(|(name, _)| name).collect()
+    }
+
+    fn state(rules: &mut Rules, state: Value) -> Vec<String> {
+        let event = Event::State { topic: BATTERY, state: &state };
+        names(rules.evaluate(&event, &HashMap::new()))
+    }
+
+    fn mqtt(rules: &mut Rules, topic: &str, payload: &str) -> Vec<String> {
+        names(rules.evaluate(&Event::Mqtt { topic, payload }, &HashMap::new()))
+    }
+
+    #[test]
+    fn rules_with_conditions_run_when_the_conditions_start_to_hold() {
+        let mut rules = rules(
+            "
+            - name: low
+              trigger: { state: varta_element_1 }
+              conditions: [{ field: state_of_charge, below: 10 }]
+              actions: [{ publish: alerts, payload: low }]
+            ",
+        );
+
+        assert!(state(&mut rules, json!({ "state_of_charge": 12 })).is_empty());
+        assert_eq!(state(&mut rules, json!({ "state_of_charge": 9 })), ["low"]);
+        assert!(state(&mut rules, json!({ "state_of_charge": 8 })).is_empty());
+        assert!(state(&mut rules, json!({ "state_of_charge": 10 })).is_empty());
+        assert_eq!(state(&mut rules, json!({ "state_of_charge": 9 })), ["low"]);
+    }
+
+    #[test]
+    fn rules_without_conditions_run_every_time() {
+        let mut rules = rules(
+            "
+            - name: forward
+              trigger: { mqtt: home/doorbell }
+              actions: [{ publish: alerts, payload: ring }]
+            ",
+        );
+
+        assert_eq!(mqtt(&mut rules, "home/doorbell", "ON"), ["forward"]);
+        assert_eq!(mqtt(&mut rules, "home/doorbell", "ON"), ["forward"]);
+        assert!(mqtt(&mut rules, "home/doorbell/battery", "ON").is_empty());
+    }
+
+    #[test]
+    fn strings_are_equal_ignoring_case() {
+        let mut rules = rules(
+            "
+            - name: discharging
+              trigger: { state: varta_element_1 }
+              conditions: [{ field: state, equals: Discharging }]
+              actions: [{ publish: alerts, payload: discharging }]
+            - name: not charging
+              trigger: { state: varta_element_1 }
+              conditions: [{ field: state, not_equals: charging }]
+              actions: [{ publish: alerts, payload: not charging }]
+            ",
+        );
+
+        assert_eq!(
+            state(&mut rules, json!({ "state": "discharging" })),
+            ["discharging", "not charging"]
+        );
+        assert!(state(&mut rules, json!({ "state": "Charging" })).is_empty());
+    }
+
+    #[test]
+    fn numbers_are_equal_to_numeric_strings() {
+        let mut rules = rules(
+            "
+            - name: level
+              trigger: { mqtt: home/tank }
+              conditions: [{ equals: 10 }]
+              actions: [{ publish: alerts, payload: full }]
+            - name: above
+              trigger: { mqtt: home/tank }
+              conditions: [{ above: 9.5 }]
+              actions: [{ publish: alerts, payload: above }]
+            ",
+        );
+
+        assert_eq!(mqtt(&mut rules, "home/tank", " 10.0 "), ["level", "above"]);
+        assert!(mqtt(&mut rules, "home/tank", "full").is_empty());
+        assert_eq!(mqtt(&mut rules, "home/tank", "10"), ["level", "above"]);
+    }
+
+    #[test]
+    fn fields_select_nested_values() {
+        let mut rules = rules(
+            "
+            - name: window
+              trigger: { mqtt: home/window }
+              conditions: [{ field: contact.state, equals: open }]
+              actions: [{ cover: velux_integra_1, command: close }]
+            ",
+        );
+
+        assert!(mqtt(&mut rules, "home/window", r#"{"contact": {"state": "closed"}}"#).is_empty());
+        assert!(mqtt(&mut rules, "home/window", r#"{"contact": "open"}"#).is_empty());
+        assert!(mqtt(&mut rules, "home/window", "open").is_empty());
+        assert_eq!(
+            mqtt(&mut rules, "home/window", r#"{"contact": {"state": "OPEN"}}"#),
+            ["window"]
+        );
+    }
+
+    #[test]
+    fn conditions_on_devices_test_their_latest_state() {
+        let mut rules = rules(
+            "
+            - name: evening
+              trigger: { sun: dusk }
+              conditions: [{ device: varta_element_1, field: state_of_charge, above: 50 }]
+              actions: [{ publish: home/light/set, payload: 'ON' }]
+            ",
+        );
+
+        let charged = HashMap::from([(BATTERY.to_owned(), json!({ "state_of_charge": 80 }))]);
+        let empty = HashMap::from([(BATTERY.to_owned(), json!({ "state_of_charge": 20 }))]);
+
+        // scheduled rules run every time their conditions hold
+        assert_eq!(names(rules.evaluate(&Event::Schedule(0), &charged)), ["evening"]);
+        assert_eq!(names(rules.evaluate(&Event::Schedule(0), &charged)), ["evening"]);
+        assert!(rules.evaluate(&Event::Schedule(0), &empty).is_empty());
+        assert!(rules.evaluate(&Event::Schedule(0), &HashMap::new()).is_empty());
+        assert!(rules.evaluate(&Event::Schedule(1), &charged).is_empty());
+    }
+
+    #[test]
+    fn inputs_trigger_on_their_edges() {
+        let mut rules = rules(
+            "
+            - name: rising
+              trigger: { input: 5 }
+              actions: [{ publish: alerts, payload: rising }]
+            - name: falling
+              trigger: { input: 6, on: falling }
+              actions: [{ publish: alerts, payload: falling }]
+            - name: both
+              trigger: { input: 7, on: both }
+              conditions: [{ equals: 'ON' }]
+              actions: [{ publish: alerts, payload: both }]
+            ",
+        );
+
+        let mut input = |rule, active| names(rules.evaluate(&Event::Input { rule, active }, &HashMap::new()));
+
+        assert_eq!(input(0, true), ["rising"]);
+        assert!(input(0, false).is_empty());
+        assert!(input(1, true).is_empty());
+        assert_eq!(input(1, false), ["falling"]);
+        assert_eq!(input(2, true), ["both"]);
+        assert!(input(2, false).is_empty());
+        assert_eq!(input(2, true), ["both"]);
+    }
+
+    #[test]
+    fn triggers_on_bridge_topics_are_rejected() {
+        let confs: Vec<RuleConfig> = serde_yaml::from_str(
+            "
+            - name: loop
+              trigger: { mqtt: bridge/velux_integra_1/set }
+              actions: [{ publish: alerts, payload: loop }]
+            ",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Rules::from_config(&confs, &devices(), "bridge"),
+            Err(Error::BridgeTopic { .. })
+        ));
+    }
+}
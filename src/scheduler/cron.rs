@@ -0,0 +1,258 @@
+use chrono::{DateTime, Datelike, Days, Duration, Local, LocalResult, NaiveDate, TimeZone, Timelike};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("expected 5 fields (minute hour day-of-month month day-of-week), got {0}")]
+    FieldCount(usize),
+    #[error("invalid {field} field {value:?}")]
+    InvalidField { field: &'static str, value: String },
+}
+
+/// Values of a single field as a bitset, `restricted` is false for fields starting with `*`.
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    values: u64,
+    restricted: bool,
+}
+
+impl Field {
+    fn parse(name: &'static str, s: &str, min: u32, max: u32) -> Result<Self, Error> {
+        let invalid = || Error::InvalidField { field: name, value: s.to_owned() };
+        let mut values = 0;
+
+        for part in s.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step.parse::<u32>().map_err(|_| invalid())?)),
+                None => (part, None),
+            };
+
+            let (start, end) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let start = range.parse().map_err(|_| invalid())?;
+                    (start, if step.is_some() { max } else { start })
+                },
+            };
+
+            if start < min || end > max || start > end || step == Some(0) {
+                return Err(invalid());
+            }
+
+            for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+                values |= 1 << value;
+            }
+        }
+
+        Ok(Self { values, restricted: !s.starts_with('*') })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+/// A cron-like schedule in local time, days match if either the day of month or the day of week matches
+/// when both are restricted.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+impl FromStr for CronSchedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(Error::FieldCount(fields.len()));
+        };
+
+        let mut weekdays = Field::parse("day-of-week", weekdays, 0, 7)?;
+
+        // both 0 and 7 are sunday
+        if weekdays.contains(7) {
+            weekdays.values |= 1;
+        }
+
+        Ok(Self {
+            minutes: Field::parse("minute", minutes, 0, 59)?,
+            hours: Field::parse("hour", hours, 0, 23)?,
+            days: Field::parse("day-of-month", days, 1, 31)?,
+            months: Field::parse("month", months, 1, 12)?,
+            weekdays,
+        })
+    }
+}
+
+impl CronSchedule {
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        let day = self.days.contains(date.day());
+        let weekday = self.weekdays.contains(date.weekday().num_days_from_sunday());
+
+        let day_matches = if self.days.restricted && self.weekdays.restricted {
+            day || weekday
+        } else {
+            day && weekday
+        };
+
+        self.months.contains(date.month()) && day_matches
+    }
+
+    /// The first matching minute after `now`, looking at most a year ahead.
+    pub fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        // truncated on the instant, as `with_second` fails for local times that are repeated when falling back
+        let start = now + Duration::minutes(1);
+        let start = start - Duration::seconds(start.second().into()) - Duration::nanoseconds(start.nanosecond().into());
+
+        for day in 0..=366 {
+            let date = start.date_naive().checked_add_days(Days::new(day))?;
+
+            if !self.matches_date(date) {
+                continue;
+            }
+
+            for hour in (0..24).filter(|&hour| self.hours.contains(hour)) {
+                for minute in (0..60).filter(|&minute| self.minutes.contains(minute)) {
+                    // skips times that don't exist because of a daylight saving time change, repeated times are
+                    // only taken the first time (chrono doesn't return ambiguous times in a consistent order)
+                    let time = match Local.from_local_datetime(&date.and_hms_opt(hour, minute, 0)?) {
+                        LocalResult::Single(time) => time,
+                        LocalResult::Ambiguous(first, second) => first.min(second),
+                        LocalResult::None => continue,
+                    };
+
+                    if time >= start {
+                        return Some(time);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Local times in a zone with daylight saving time, every test that uses `Local` sets the same zone.
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        std::env::set_var("TZ", "Europe/Berlin");
+
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .single()
+            .unwrap()
+    }
+
+    fn next(cron: &str, now: DateTime<Local>) -> DateTime<Local> {
+        cron.parse::<CronSchedule>().unwrap().next_after(now).unwrap()
+    }
+
+    #[test]
+    fn step() {
+        assert_eq!(
+            next("*/15 * * * *", local(2024, 5, 6, 10, 0)),
+            local(2024, 5, 6, 10, 15)
+        );
+        assert_eq!(
+            next("*/15 * * * *", local(2024, 5, 6, 10, 50)),
+            local(2024, 5, 6, 11, 0)
+        );
+        assert_eq!(
+            next("5/20 * * * *", local(2024, 5, 6, 10, 30)),
+            local(2024, 5, 6, 10, 45)
+        );
+    }
+
+    #[test]
+    fn range() {
+        // weekdays from 7 to 9, 2024-05-04 is a saturday
+        let cron = "0 7-9 * * 1-5";
+        assert_eq!(next(cron, local(2024, 5, 3, 9, 0)), local(2024, 5, 6, 7, 0));
+        assert_eq!(next(cron, local(2024, 5, 6, 7, 0)), local(2024, 5, 6, 8, 0));
+        assert_eq!(
+            next("30 8-18/5 * * *", local(2024, 5, 6, 13, 30)),
+            local(2024, 5, 6, 18, 30)
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            next("0,30 6,18 * * *", local(2024, 5, 6, 6, 30)),
+            local(2024, 5, 6, 18, 0)
+        );
+    }
+
+    #[test]
+    fn day_of_week_7_is_sunday() {
+        // 2024-05-05 is a sunday
+        assert_eq!(next("0 12 * * 7", local(2024, 5, 1, 0, 0)), local(2024, 5, 5, 12, 0));
+        assert_eq!(next("0 12 * * 0", local(2024, 5, 1, 0, 0)), local(2024, 5, 5, 12, 0));
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // both restricted: the 15th or any monday, 2024-05-06 is a monday
+        let cron = "0 0 15 * 1";
+        assert_eq!(next(cron, local(2024, 5, 1, 12, 0)), local(2024, 5, 6, 0, 0));
+        assert_eq!(next(cron, local(2024, 5, 13, 12, 0)), local(2024, 5, 15, 0, 0));
+
+        // only the day of month restricted: the day of week doesn't widen it
+        assert_eq!(next("0 0 15 * *", local(2024, 5, 1, 12, 0)), local(2024, 5, 15, 0, 0));
+    }
+
+    #[test]
+    fn month() {
+        assert_eq!(next("0 0 1 1 *", local(2024, 5, 1, 12, 0)), local(2025, 1, 1, 0, 0));
+    }
+
+    #[test]
+    fn spring_forward_gap() {
+        // on 2024-03-31 the clock jumps from 02:00 to 03:00, times in between don't exist
+        assert_eq!(next("30 2 * * *", local(2024, 3, 31, 1, 0)), local(2024, 4, 1, 2, 30));
+        assert_eq!(next("0 * * * *", local(2024, 3, 31, 1, 30)), local(2024, 3, 31, 3, 0));
+    }
+
+    #[test]
+    fn fall_back_runs_once() {
+        // on 2024-10-27 the clock is set back from 03:00 to 02:00, the first 02:30 is taken
+        let first = next("30 2 * * *", local(2024, 10, 27, 1, 0));
+        assert_eq!(first.to_rfc3339(), "2024-10-27T02:30:00+02:00");
+        assert_eq!(next("30 2 * * *", first), local(2024, 10, 28, 2, 30));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!("* * * *".parse::<CronSchedule>(), Err(Error::FieldCount(4))));
+        assert!(matches!(
+            "60 * * * *".parse::<CronSchedule>(),
+            Err(Error::InvalidField { field: "minute", .. })
+        ));
+        assert!(matches!(
+            "* * 0 * *".parse::<CronSchedule>(),
+            Err(Error::InvalidField { field: "day-of-month", .. })
+        ));
+        assert!(matches!(
+            "*/0 * * * *".parse::<CronSchedule>(),
+            Err(Error::InvalidField { field: "minute", .. })
+        ));
+        assert!(matches!(
+            "* 5-2 * * *".parse::<CronSchedule>(),
+            Err(Error::InvalidField { field: "hour", .. })
+        ));
+    }
+}
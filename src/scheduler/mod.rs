@@ -0,0 +1,97 @@
+pub mod cron;
+pub mod solar;
+
+use crate::{
+    config::{ScheduleConfig, SolarEvent, TriggerConfig},
+    covers::CoverCommand,
+};
+use chrono::{DateTime, Days, Duration, Local};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("invalid cron expression: {0}")]
+    Cron(#[from] cron::Error),
+    #[error("solar triggers need the latitude and longitude of the scheduler")]
+    MissingLocation,
+}
+
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    Cron(cron::CronSchedule),
+    Solar {
+        event: SolarEvent,
+        offset: Duration,
+        latitude: f64,
+        longitude: f64,
+    },
+}
+
+impl Trigger {
+    pub fn from_config(conf: &TriggerConfig, location: Option<(f64, f64)>) -> Result<Self, Error> {
+        match conf {
+            TriggerConfig::Cron { cron } => Ok(Trigger::Cron(cron.parse()?)),
+            TriggerConfig::Solar { sun, offset_minutes } => {
+                let Some((latitude, longitude)) = location else {
+                    return Err(Error::MissingLocation);
+                };
+
+                Ok(Trigger::Solar {
+                    event: *sun,
+                    offset: Duration::minutes(*offset_minutes),
+                    latitude,
+                    longitude,
+                })
+            },
+        }
+    }
+
+    /// The next time the trigger fires after `now`, looking at most a year ahead.
+    pub fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Trigger::Cron(cron) => cron.next_after(now),
+            Trigger::Solar { event, offset, latitude, longitude } => {
+                // start a day early, the offset might move the event of the next day before now
+                let first = now.date_naive().checked_sub_days(Days::new(1))?;
+
+                (0..=367)
+                    .filter_map(|day| first.checked_add_days(Days::new(day)))
+                    .filter_map(|date| solar::event_time(*event, date, *latitude, *longitude))
+                    .map(|time| time.with_timezone(&Local) + *offset)
+                    .find(|&time| time > now)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ScheduleAction {
+    /// issue the command to the covers with the command topics
+    Cover { covers: Vec<String>, command: CoverCommand },
+    /// evaluate the rule with the index
+    Rule(usize),
+}
+
+/// An action that is run whenever its trigger fires, as long as the schedule is enabled.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub name: String,
+    pub action: ScheduleAction,
+    pub trigger: Trigger,
+    pub enabled: bool,
+}
+
+impl Schedule {
+    pub fn from_config(
+        conf: &ScheduleConfig,
+        location: Option<(f64, f64)>,
+        covers: Vec<String>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            name: conf.name.clone(),
+            action: ScheduleAction::Cover { covers, command: conf.command.into() },
+            trigger: Trigger::from_config(&conf.trigger, location)?,
+            enabled: conf.enabled,
+        })
+    }
+}
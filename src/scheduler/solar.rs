@@ -0,0 +1,94 @@
+use crate::config::SolarEvent;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+
+/// Time of `event` on `date` at the given location, computed with the sunrise equation.
+/// `None` if the sun doesn't cross the respective elevation that day (polar day or night).
+pub fn event_time(event: SolarEvent, date: NaiveDate, latitude: f64, longitude: f64) -> Option<DateTime<Utc>> {
+    let days = (date - NaiveDate::from_ymd_opt(2000, 1, 1)?).num_days() as f64;
+    let mean_solar_noon = days - longitude / 360.0;
+
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_noon).rem_euclid(360.0).to_radians();
+    let center = 1.9148 * mean_anomaly.sin() + 0.02 * (2.0 * mean_anomaly).sin() + 0.0003 * (3.0 * mean_anomaly).sin();
+    let ecliptic_longitude = (mean_anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+
+    let transit = J2000 + mean_solar_noon + 0.0053 * mean_anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397_f64.to_radians().sin()).asin();
+
+    let elevation = match event {
+        SolarEvent::Sunrise | SolarEvent::Sunset => -0.833_f64,
+        SolarEvent::Dawn | SolarEvent::Dusk => -6.0,
+    }
+    .to_radians();
+
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = (elevation.sin() - latitude.sin() * declination.sin()) / (latitude.cos() * declination.cos());
+
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+
+    let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+
+    let julian_day = match event {
+        SolarEvent::Dawn | SolarEvent::Sunrise => transit - hour_angle,
+        SolarEvent::Sunset | SolarEvent::Dusk => transit + hour_angle,
+    };
+
+    Utc.timestamp_millis_opt(((julian_day - UNIX_EPOCH_JULIAN_DAY) * 86_400_000.0).round() as i64)
+        .single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BERLIN: (f64, f64) = (52.52, 13.405);
+    const TROMSO: (f64, f64) = (69.65, 18.96);
+
+    fn assert_close(event: SolarEvent, date: (i32, u32, u32), (latitude, longitude): (f64, f64), expected: &str) {
+        let date = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap();
+        let time = event_time(event, date, latitude, longitude).unwrap();
+        let expected = DateTime::parse_from_rfc3339(expected).unwrap();
+        let difference = (time - expected.with_timezone(&Utc)).num_seconds().abs();
+
+        assert!(difference <= 120, "{event:?} on {date} at {time}, expected {expected}");
+    }
+
+    #[test]
+    fn berlin_summer_solstice() {
+        assert_close(SolarEvent::Sunrise, (2024, 6, 21), BERLIN, "2024-06-21T04:43:00+02:00");
+        assert_close(SolarEvent::Sunset, (2024, 6, 21), BERLIN, "2024-06-21T21:33:00+02:00");
+    }
+
+    #[test]
+    fn berlin_winter_solstice() {
+        assert_close(SolarEvent::Sunrise, (2024, 12, 21), BERLIN, "2024-12-21T08:15:00+01:00");
+        assert_close(SolarEvent::Sunset, (2024, 12, 21), BERLIN, "2024-12-21T15:54:00+01:00");
+    }
+
+    #[test]
+    fn twilight_surrounds_sunrise_and_sunset() {
+        let date = NaiveDate::from_ymd_opt(2024, 3, 20).unwrap();
+        let (latitude, longitude) = BERLIN;
+        let time = |event| event_time(event, date, latitude, longitude).unwrap();
+
+        assert!(time(SolarEvent::Dawn) < time(SolarEvent::Sunrise));
+        assert!(time(SolarEvent::Sunrise) < time(SolarEvent::Sunset));
+        assert!(time(SolarEvent::Sunset) < time(SolarEvent::Dusk));
+    }
+
+    #[test]
+    fn polar_day_and_night() {
+        let (latitude, longitude) = TROMSO;
+        let summer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let winter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+
+        assert_eq!(event_time(SolarEvent::Sunrise, summer, latitude, longitude), None);
+        assert_eq!(event_time(SolarEvent::Sunset, winter, latitude, longitude), None);
+    }
+}
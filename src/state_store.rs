@@ -0,0 +1,95 @@
+use crate::config::StateStoreConfig;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// Last known state of every device, keyed by the topic the device is addressed by,
+/// persisted to disk so that it can be restored after a restart.
+pub struct StateStore {
+    path: PathBuf,
+    save_interval: Duration,
+    last_saved: HashMap<String, Value>,
+}
+
+impl StateStore {
+    pub fn new(conf: StateStoreConfig) -> Self {
+        Self {
+            path: conf.path,
+            save_interval: Duration::from_millis(conf.save_interval_ms),
+            last_saved: HashMap::new(),
+        }
+    }
+
+    pub fn save_interval(&self) -> Duration {
+        self.save_interval
+    }
+
+    /// Loads the persisted states, every restored state is marked as `stale` until the device refreshes it.
+    pub fn restore(&mut self) -> HashMap<String, Value> {
+        let mut states = match self.load() {
+            Ok(states) => states,
+            Err(e) => {
+                warn!(path = %self.path.display(), "Unable to restore device states: {e}");
+                return HashMap::new();
+            },
+        };
+
+        info!(devices = states.len(), "Restored device states");
+        self.last_saved = states.clone();
+
+        for state in states.values_mut() {
+            if let Value::Object(fields) = state {
+                fields.insert("stale".to_owned(), Value::Bool(true));
+            }
+        }
+
+        states
+    }
+
+    /// Writes the states to disk, unless nothing changed since they were last saved.
+    pub fn save(&mut self, states: &HashMap<String, Value>) {
+        if *states == self.last_saved {
+            return;
+        }
+
+        match self.store(states) {
+            Ok(()) => self.last_saved = states.clone(),
+            Err(e) => warn!(path = %self.path.display(), "Unable to save device states: {e}"),
+        }
+    }
+
+    fn load(&self) -> io::Result<HashMap<String, Value>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    fn store(&self, states: &HashMap<String, Value>) -> io::Result<()> {
+        write_atomically(&self.path, |writer| Ok(serde_json::to_writer_pretty(writer, states)?))
+    }
+}
+
+/// Replaces the file at `path` with what `write` writes, readers either see the old or the new content.
+/// The content is written to a temporary file next to it, synced and then renamed over the file.
+pub fn write_atomically(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.into_inner()?.sync_all()?;
+    }
+
+    fs::rename(tmp_path, path)
+}
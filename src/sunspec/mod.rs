@@ -1,7 +1,9 @@
 pub mod varta;
 
 pub type Quantity = u16;
-pub type Watts = u16;
-pub type VoltAmps = u16;
+pub type Watts = u32;
+pub type VoltAmps = u32;
+pub type SignedWatts = i32;
+pub type SignedVoltAmps = i32;
 pub type Percentage = u16;
 pub type WattHours = u32;
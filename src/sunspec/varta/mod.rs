@@ -1,10 +1,10 @@
 mod registers;
 
-use super::{Percentage, Quantity, WattHours, Watts};
-use crate::sunspec::VoltAmps;
+use super::{Percentage, Quantity, SignedVoltAmps, SignedWatts, WattHours, Watts};
+use crate::{config, sunspec::VoltAmps};
 use modbus::{Modbus, Register};
 use serde::Serialize;
-use std::net::SocketAddr;
+use std::net::{AddrParseError, SocketAddr};
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum GridPower {
@@ -12,12 +12,32 @@ pub enum GridPower {
     Consumption(Watts),
 }
 
+impl GridPower {
+    pub fn from_signed(value: SignedWatts) -> Option<Self> {
+        match value {
+            ..=-1 => Some(GridPower::Consumption(value.unsigned_abs())),
+            0 => None,
+            1.. => Some(GridPower::Backfeed(value.unsigned_abs())),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum BatteryPower<T> {
     Charge(T),
     Discharge(T),
 }
 
+impl BatteryPower<Watts> {
+    pub fn from_signed(value: SignedWatts) -> Option<Self> {
+        match value {
+            ..=-1 => Some(BatteryPower::Discharge(value.unsigned_abs())),
+            0 => None,
+            1.. => Some(BatteryPower::Charge(value.unsigned_abs())),
+        }
+    }
+}
+
 pub type ActiveBatteryPower = BatteryPower<Watts>;
 pub type ApparentBatteryPower = BatteryPower<VoltAmps>;
 
@@ -54,14 +74,78 @@ impl TryFrom<u16> for State {
     }
 }
 
+/// Power values are signed as reported by the device: positive battery power is charging,
+/// positive grid power is backfeed. `None` means the value is currently unknown.
 #[derive(Debug, Copy, Clone)]
 pub struct Measurements {
     pub state: State,
     pub state_of_charge: Percentage,
     pub total_charge_energy: WattHours,
-    pub active_battery_power: Option<ActiveBatteryPower>,
-    pub apparent_battery_power: Option<ApparentBatteryPower>,
-    pub grid_power: Option<GridPower>,
+    pub active_battery_power: Option<SignedWatts>,
+    pub apparent_battery_power: Option<SignedVoltAmps>,
+    pub grid_power: Option<SignedWatts>,
+}
+
+impl Measurements {
+    pub fn active_battery_power(&self) -> Option<ActiveBatteryPower> {
+        self.active_battery_power.and_then(BatteryPower::from_signed)
+    }
+
+    pub fn apparent_battery_power(&self) -> Option<ApparentBatteryPower> {
+        self.apparent_battery_power.and_then(BatteryPower::from_signed)
+    }
+
+    pub fn grid_power(&self) -> Option<GridPower> {
+        self.grid_power.and_then(GridPower::from_signed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PowerRegisters {
+    pub active_power: Register,
+    pub apparent_power: Register,
+    pub grid_power: Register,
+}
+
+impl Default for PowerRegisters {
+    fn default() -> Self {
+        Self {
+            active_power: registers::ACTIVE_POWER,
+            apparent_power: registers::APPARENT_POWER,
+            grid_power: registers::GRID_POWER,
+        }
+    }
+}
+
+impl From<&config::PowerRegistersConfig> for PowerRegisters {
+    fn from(conf: &config::PowerRegistersConfig) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            active_power: conf
+                .active_power
+                .as_ref()
+                .map_or(defaults.active_power, config::RegisterConfig::register),
+            apparent_power: conf
+                .apparent_power
+                .as_ref()
+                .map_or(defaults.apparent_power, config::RegisterConfig::register),
+            grid_power: conf
+                .grid_power
+                .as_ref()
+                .map_or(defaults.grid_power, config::RegisterConfig::register),
+        }
+    }
+}
+
+/// Decodes a signed power register, either a single 16 bit word or a 32 bit value
+/// with the lower word first (same word order as `TOTAL_CHARGE_ENERGY`).
+fn decode_signed(words: &[u16]) -> i32 {
+    match *words {
+        [word] => word as i16 as i32,
+        [lower_word, upper_word] => (lower_word as u32 | ((upper_word as u32) << 16)) as i32,
+        _ => unreachable!("power registers are either one or two words wide"),
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -77,11 +161,19 @@ pub struct DeviceSpecifications {
 
 pub struct ElementSunspecClient {
     client: Modbus,
+    power_registers: PowerRegisters,
 }
 
 impl ElementSunspecClient {
-    pub fn new(addr: SocketAddr) -> Self {
-        Self { client: Modbus::new(addr) }
+    pub fn new(addr: SocketAddr, power_registers: PowerRegisters) -> Self {
+        Self { client: Modbus::new(addr), power_registers }
+    }
+
+    pub fn from_config(conf: &config::SunspecConfig) -> Result<Self, AddrParseError> {
+        Ok(Self::new(
+            SocketAddr::new(conf.host.parse()?, conf.host_port),
+            (&conf.power_registers).into(),
+        ))
     }
 
     pub async fn specifications(&mut self) -> modbus::Result<DeviceSpecifications> {
@@ -115,56 +207,84 @@ impl ElementSunspecClient {
         })
     }
 
+    async fn read_signed(&mut self, dynamic_range1: &[u16], reg: Register) -> modbus::Result<i32> {
+        if registers::is_register_in_address_range(registers::DYNAMIC_REGISTER_RANGE1, reg.clone()) {
+            let start = registers::DYNAMIC_REGISTER_RANGE1.start;
+            Ok(decode_signed(
+                &dynamic_range1[(reg.start - start) as usize..(reg.end - start) as usize],
+            ))
+        } else {
+            Ok(decode_signed(&self.client.read_input_registers(reg).await?))
+        }
+    }
+
     pub async fn measure(&mut self) -> modbus::Result<Measurements> {
-        let response1 = self
+        let response = self
             .client
-            .read_input_registers(registers::STATE.start..registers::TOTAL_CHARGE_ENERGY.end)
+            .read_input_registers(registers::DYNAMIC_REGISTER_RANGE1)
             .await?;
 
-        let response2 = self.client.read_input_registers(registers::GRID_POWER).await?;
-
         let slice = |reg: Register| {
-            if reg == registers::GRID_POWER {
-                &response2[..]
-            } else {
-                &response1[(reg.start - registers::STATE.start) as usize..(reg.end - registers::STATE.start) as usize]
-            }
+            &response[(reg.start - registers::DYNAMIC_REGISTER_RANGE1.start) as usize
+                ..(reg.end - registers::DYNAMIC_REGISTER_RANGE1.start) as usize]
+        };
+
+        let state = slice(registers::STATE)[0].try_into().unwrap();
+        let state_of_charge = slice(registers::STATE_OF_CHARGE)[0];
+        let total_charge_energy = {
+            let slice = slice(registers::TOTAL_CHARGE_ENERGY);
+            let lower_word = slice[0];
+            let upper_word = slice[1];
+
+            lower_word as u32 | ((upper_word as u32) << 16)
         };
 
+        let PowerRegisters { active_power, apparent_power, grid_power } = self.power_registers.clone();
+
         Ok(Measurements {
-            state: slice(registers::STATE)[0].try_into().unwrap(),
-            active_battery_power: {
-                let value = slice(registers::ACTIVE_POWER)[0] as i16;
-                match value {
-                    ..=-1 => Some(BatteryPower::Discharge(value.unsigned_abs())),
-                    0 => None,
-                    1.. => Some(BatteryPower::Charge(value as u16)),
-                }
-            },
-            apparent_battery_power: {
-                let value = slice(registers::APPARENT_POWER)[0] as i16;
-                match value {
-                    ..=-1 => Some(BatteryPower::Discharge(value.unsigned_abs())),
-                    0 => None,
-                    1.. => Some(BatteryPower::Charge(value as u16)),
-                }
-            },
-            state_of_charge: slice(registers::STATE_OF_CHARGE)[0],
-            total_charge_energy: {
-                let slice = slice(registers::TOTAL_CHARGE_ENERGY);
-                let lower_word = slice[0];
-                let upper_word = slice[1];
-
-                lower_word as u32 | ((upper_word as u32) << 16)
-            },
-            grid_power: {
-                let value = slice(registers::GRID_POWER)[0] as i16;
-                match value {
-                    ..=-1 => Some(GridPower::Consumption(value.unsigned_abs())),
-                    0 => None,
-                    1.. => Some(GridPower::Backfeed(value as u16)),
-                }
-            },
+            state,
+            state_of_charge,
+            total_charge_energy,
+            active_battery_power: Some(self.read_signed(&response, active_power).await?),
+            apparent_battery_power: Some(self.read_signed(&response, apparent_power).await?),
+            grid_power: Some(self.read_signed(&response, grid_power).await?),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_single_word() {
+        assert_eq!(decode_signed(&[1500]), 1500);
+        assert_eq!(decode_signed(&[0xfa24]), -1500);
+        assert_eq!(decode_signed(&[0x8000]), i16::MIN as i32);
+    }
+
+    #[test]
+    fn decode_two_words_lower_word_first() {
+        assert_eq!(decode_signed(&[0x86a0, 0x0001]), 100_000);
+        assert_eq!(decode_signed(&[0x7960, 0xfffe]), -100_000);
+        assert_eq!(decode_signed(&[0xffff, 0xffff]), -1);
+        assert_eq!(decode_signed(&[0x0000, 0x8000]), i32::MIN);
+    }
+
+    #[test]
+    fn battery_power_from_two_word_values() {
+        assert_eq!(
+            BatteryPower::from_signed(decode_signed(&[0x7960, 0xfffe])),
+            Some(BatteryPower::Discharge(100_000))
+        );
+        assert_eq!(
+            BatteryPower::from_signed(decode_signed(&[0x86a0, 0x0001])),
+            Some(BatteryPower::Charge(100_000))
+        );
+        assert_eq!(BatteryPower::from_signed(decode_signed(&[0, 0])), None);
+        assert_eq!(
+            BatteryPower::from_signed(i32::MIN),
+            Some(BatteryPower::Discharge(i32::MIN.unsigned_abs()))
+        );
+    }
+}